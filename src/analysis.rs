@@ -0,0 +1,118 @@
+use primitive_types::U256;
+
+/// Bitmap of the offsets in a contract's code that are valid jump destinations.
+pub struct JumpDests {
+    bits: Vec<u64>,
+    len: usize,
+}
+
+impl JumpDests {
+    /// Scans `code` for JUMPDEST instructions, skipping over the immediate data of PUSH
+    /// instructions so that a 0x5B byte inside of it is never considered a destination.
+    pub fn analyze(code: &[u8]) -> Self {
+        let mut bits = vec![0u64; code.len().div_ceil(64)];
+        let mut pc = 0;
+
+        while pc < code.len() {
+            match code[pc] {
+                0x5B => bits[pc / 64] |= 1 << (pc % 64),
+                op @ 0x60..=0x7F => pc += (op - 0x5F) as usize,
+                _ => (),
+            }
+
+            pc += 1;
+        }
+
+        Self {
+            bits,
+            len: code.len(),
+        }
+    }
+
+    pub fn contains(&self, dest: U256) -> bool {
+        if dest >= self.len.into() {
+            return false;
+        }
+
+        let dest = dest.as_usize();
+        self.bits[dest / 64] & (1 << (dest % 64)) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jumpdest_in_push_data() {
+        // PUSH2 0x5B5B, JUMPDEST
+        let jumpdests = JumpDests::analyze(&[0x61, 0x5B, 0x5B, 0x5B]);
+
+        assert!(!jumpdests.contains(0.into()));
+        assert!(!jumpdests.contains(1.into()));
+        assert!(!jumpdests.contains(2.into()));
+        assert!(jumpdests.contains(3.into()));
+    }
+
+    #[test]
+    fn jumpdest_in_push32_data() {
+        let mut code = vec![0x7F];
+        code.extend_from_slice(&[0x5B; 32]);
+        code.push(0x5B);
+
+        let jumpdests = JumpDests::analyze(&code);
+
+        for i in 0..33 {
+            assert!(!jumpdests.contains(i.into()));
+        }
+        assert!(jumpdests.contains(33.into()));
+    }
+
+    #[test]
+    fn truncated_push() {
+        // JUMPDEST, PUSH4 with only two bytes of data left
+        let jumpdests = JumpDests::analyze(&[0x5B, 0x63, 0x5B, 0x5B]);
+
+        assert!(jumpdests.contains(0.into()));
+        assert!(!jumpdests.contains(2.into()));
+        assert!(!jumpdests.contains(3.into()));
+    }
+
+    #[test]
+    fn push_at_the_end() {
+        let jumpdests = JumpDests::analyze(&[0x5B, 0x7F]);
+
+        assert!(jumpdests.contains(0.into()));
+        assert!(!jumpdests.contains(1.into()));
+    }
+
+    #[test]
+    fn destination_past_the_code() {
+        let mut code = vec![0x5B; 64];
+        code[63] = 0x00;
+
+        let jumpdests = JumpDests::analyze(&code);
+
+        assert!(jumpdests.contains(62.into()));
+        assert!(!jumpdests.contains(63.into()));
+        assert!(!jumpdests.contains(64.into()));
+        assert!(!jumpdests.contains(65.into()));
+        assert!(!jumpdests.contains(128.into()));
+    }
+
+    #[test]
+    fn destination_above_usize() {
+        let jumpdests = JumpDests::analyze(&[0x5B]);
+
+        assert!(!jumpdests.contains(U256::from(usize::MAX) + 1));
+        assert!(!jumpdests.contains(U256::one() << 64));
+        assert!(!jumpdests.contains(U256::MAX));
+    }
+
+    #[test]
+    fn empty_code() {
+        let jumpdests = JumpDests::analyze(&[]);
+
+        assert!(!jumpdests.contains(0.into()));
+    }
+}
@@ -1,13 +1,18 @@
 use primitive_types::{H160, U256};
 
+#[derive(Default)]
 pub struct Environment {
     // chain info
     pub chain_id: U256,
     pub coinbase: H160,
+    // the host fills it in for the fork, the difficulty before the merge and the prevrandao after
+    // it (EIP-4399)
     pub difficulty: U256,
     pub block_number: U256,
     pub timestamp: U256,
     pub gas_limit: U256,
+    pub base_fee: U256,
+    pub blob_base_fee: U256,
 
     // tx context
     pub gas_price: U256,
@@ -19,10 +24,12 @@ impl From<evmc_vm::ffi::evmc_tx_context> for Environment {
         Environment {
             chain_id: U256::from_big_endian(&ctx.chain_id.bytes),
             coinbase: H160::from_slice(&ctx.block_coinbase.bytes),
-            difficulty: U256::from_big_endian(&ctx.block_difficulty.bytes),
+            difficulty: U256::from_big_endian(&ctx.block_prev_randao.bytes),
             block_number: U256::from(ctx.block_number),
             timestamp: U256::from(ctx.block_timestamp),
             gas_limit: U256::from(ctx.block_gas_limit),
+            base_fee: U256::from_big_endian(&ctx.block_base_fee.bytes),
+            blob_base_fee: U256::from_big_endian(&ctx.blob_base_fee.bytes),
             gas_price: U256::from_big_endian(&ctx.tx_gas_price.bytes),
             origin: H160::from_slice(&ctx.tx_origin.bytes),
         }
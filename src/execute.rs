@@ -1,25 +1,46 @@
+use crate::analysis::JumpDests;
 use crate::env::Environment;
+use crate::fork::Fork;
+use crate::gas::GasSchedule;
 use crate::interrupt::{Exit, Interrupt, Yield};
-use crate::machine::Machine;
-use crate::message::{Kind as MessageKind, Message};
+use crate::machine::{Machine, MAX_CALL_DEPTH};
+use crate::message::Message;
 
 use evmc_declare::evmc_declare_vm;
-use evmc_vm::{EvmcVm, ExecutionContext, ExecutionResult, Revision};
-use log::info;
+use evmc_vm::{
+    Bytes32, EvmcVm, ExecutionContext, ExecutionResult, Revision, SetOptionError, StatusCode,
+};
+use log::{debug, info};
 use primitive_types::U256;
 
 #[evmc_declare_vm("fast_evm", "evm", "6.3.0-dev")]
-pub struct Vm;
+pub struct Vm {
+    // prices set through evmc options, applied over the schedule of whichever revision runs
+    gas: Vec<(String, u64)>,
+}
 
 impl EvmcVm for Vm {
     fn init() -> Self {
         env_logger::init();
-        Vm {}
+        Vm { gas: vec![] }
+    }
+
+    /// Every option is a price of the gas schedule, see `GasSchedule::set` for their names.
+    fn set_option(&mut self, key: &str, value: &str) -> Result<(), SetOptionError> {
+        let value = value
+            .parse::<u64>()
+            .map_err(|_| SetOptionError::InvalidValue)?;
+
+        // an unknown name is refused now rather than on the next execution
+        GasSchedule::frontier().set(key, value)?;
+        self.gas.push((key.to_owned(), value));
+
+        Ok(())
     }
 
     fn execute<'a>(
         &self,
-        _revision: Revision,
+        revision: Revision,
         code: &'a [u8],
         msg: &'a Message,
         context: Option<&'a mut ExecutionContext<'a>>,
@@ -30,18 +51,29 @@ impl EvmcVm for Vm {
 
         let context = context.unwrap();
 
-        if msg.kind() != MessageKind::EVMC_CALL {
-            return ExecutionResult::failure();
+        // the host should never start a frame past the limit, it is refused if it does
+        if msg.depth() > MAX_CALL_DEPTH {
+            return Exit::CallOverflow.to_result(0, 0, &[]);
         }
 
         if code.len() == 0 {
-            return ExecutionResult::success(msg.gas(), None);
+            return ExecutionResult::success(msg.gas(), 0, None);
         }
 
         let tx_context = context.get_tx_context().clone();
         let env = Environment::from(tx_context.clone());
 
-        let mut m = Machine::new(code, msg, &env);
+        let jumpdests = JumpDests::analyze(code);
+
+        let fork = Fork::from(revision);
+        let mut schedule = GasSchedule::from(fork);
+        for (name, value) in &self.gas {
+            schedule
+                .set(name, *value)
+                .expect("options are checked when they are set");
+        }
+
+        let mut m = Machine::new(fork, &schedule, code, &jumpdests, msg, &env);
 
         loop {
             let i = m.run();
@@ -55,7 +87,7 @@ impl EvmcVm for Vm {
                         k.to_big_endian(&mut raw);
 
                         let item = context.get_storage(
-                            msg.destination(),
+                            msg.recipient(),
                             &evmc_vm::ffi::evmc_bytes32 { bytes: raw },
                         );
 
@@ -68,25 +100,125 @@ impl EvmcVm for Vm {
                         let mut v_raw = [0; 32];
                         v.to_big_endian(&mut v_raw);
 
-                        context.set_storage(
-                            msg.destination(),
+                        let status = context.set_storage(
+                            msg.recipient(),
                             &evmc_vm::ffi::evmc_bytes32 { bytes: k_raw },
                             &evmc_vm::ffi::evmc_bytes32 { bytes: v_raw },
                         );
+
+                        if let Err(e) = m.charge_sstore(status) {
+                            return e.to_result(m.gas as i64, m.refund as i64, &m.memory);
+                        }
+                    }
+                    // the host keeps the transient storage, it is dropped at the end of the
+                    // transaction and journaled along with the rest of the state so that a frame
+                    // that fails takes its writes with it
+                    Yield::TLoad(k) => {
+                        let item = context
+                            .get_transient_storage(msg.recipient(), &Bytes32 { bytes: k.into() });
+
+                        m.stack.push(U256::from_big_endian(&item.bytes));
+                    }
+                    Yield::TStore(k, v) => {
+                        context.set_transient_storage(
+                            msg.recipient(),
+                            &Bytes32 { bytes: k.into() },
+                            &Bytes32 { bytes: v.into() },
+                        );
+                    }
+                    Yield::Log(data, topics) => {
+                        let topics: Vec<Bytes32> = topics
+                            .into_iter()
+                            .map(|t| Bytes32 { bytes: t.into() })
+                            .collect();
+
+                        context.emit_log(msg.recipient(), &data, &topics);
+                    }
+                    Yield::Balance(address) => {
+                        let balance = context.get_balance(&address);
+                        m.stack.push(U256::from_big_endian(&balance.bytes));
+                    }
+                    Yield::ExtCodeSize(address) => {
+                        m.stack.push(context.get_code_size(&address).into());
+                    }
+                    Yield::ExtCodeCopy(address, mem_begin, code_begin, len) => {
+                        let buffer = &mut m.memory[mem_begin..mem_begin + len];
+                        let copied = context.copy_code(&address, code_begin, buffer);
+
+                        // zero pad whatever lies past the end of the code
+                        for b in buffer[copied..].iter_mut() {
+                            *b = 0;
+                        }
+                    }
+                    Yield::ExtCodeHash(address) => {
+                        let hash = context.get_code_hash(&address);
+                        m.stack.push(U256::from_big_endian(&hash.bytes));
+                    }
+                    Yield::BlockHash(number) => {
+                        let hash = context.get_block_hash(number);
+                        m.stack.push(U256::from_big_endian(&hash.bytes));
+                    }
+                    Yield::AccessAccount(address) => {
+                        m.access = Some(context.access_account(&address));
+                    }
+                    Yield::AccessStorage(k) => {
+                        m.access = Some(
+                            context.access_storage(msg.recipient(), &Bytes32 { bytes: k.into() }),
+                        );
+                    }
+                    Yield::AccountExists(address) => {
+                        m.exists = Some(context.account_exists(&address));
+                    }
+                    Yield::AccountBalance(address) => {
+                        let balance = context.get_balance(&address);
+                        m.balance = Some(U256::from_big_endian(&balance.bytes));
+                    }
+                    Yield::Call(child, out_begin, out_len) => {
+                        let result = context.call(&child);
+                        m.return_from_call(&result, out_begin, out_len);
+                    }
+                    Yield::Create(child) => {
+                        let result = context.call(&child);
+
+                        m.gas += result.gas_left() as u64;
+                        if result.status_code() == StatusCode::EVMC_SUCCESS {
+                            m.refund += result.gas_refund() as isize;
+                        }
+
+                        // only a failed create leaves data behind
+                        m.return_data = match result.status_code() {
+                            StatusCode::EVMC_SUCCESS => vec![],
+                            _ => result.output().cloned().unwrap_or_default(),
+                        };
+
+                        match result.create_address() {
+                            Some(address) if result.status_code() == StatusCode::EVMC_SUCCESS => {
+                                m.stack.push(U256::from(&address.bytes[..]))
+                            }
+                            _ => m.stack.push(U256::zero()),
+                        }
                     }
-                    _ => unimplemented!(),
                 },
                 Interrupt::Exit(Exit::SelfDestruct(beneficiary)) => {
-                    context.selfdestruct(
-                        msg.destination(),
+                    // only the first selfdestruct of an account in a transaction is refunded
+                    let first = context.selfdestruct(
+                        msg.recipient(),
                         &evmc_vm::ffi::evmc_address {
                             bytes: beneficiary.to_fixed_bytes(),
                         },
                     );
 
-                    return ExecutionResult::success(m.gas as i64, None);
+                    if first {
+                        m.refund += schedule.selfdestruct_refund;
+                    }
+
+                    debug!("gas refund: {}", m.refund);
+                    return ExecutionResult::success(m.gas as i64, m.refund as i64, None);
+                }
+                Interrupt::Exit(e) => {
+                    debug!("gas refund: {}", m.refund);
+                    return e.to_result(m.gas as i64, m.refund as i64, &m.memory);
                 }
-                Interrupt::Exit(e) => return e.to_result(m.gas as i64, &m.memory),
             }
         }
     }
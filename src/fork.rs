@@ -0,0 +1,74 @@
+use evmc_vm::Revision;
+
+/// Ethereum hard forks, ordered by activation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Fork {
+    Frontier,
+    Homestead,
+    TangerineWhistle,
+    SpuriousDragon,
+    Byzantium,
+    Constantinople,
+    Petersburg,
+    Istanbul,
+    Berlin,
+    London,
+    Paris,
+    Shanghai,
+    Cancun,
+}
+
+impl From<Revision> for Fork {
+    fn from(revision: Revision) -> Fork {
+        match revision {
+            Revision::EVMC_FRONTIER => Fork::Frontier,
+            Revision::EVMC_HOMESTEAD => Fork::Homestead,
+            Revision::EVMC_TANGERINE_WHISTLE => Fork::TangerineWhistle,
+            Revision::EVMC_SPURIOUS_DRAGON => Fork::SpuriousDragon,
+            Revision::EVMC_BYZANTIUM => Fork::Byzantium,
+            Revision::EVMC_CONSTANTINOPLE => Fork::Constantinople,
+            Revision::EVMC_PETERSBURG => Fork::Petersburg,
+            Revision::EVMC_ISTANBUL => Fork::Istanbul,
+            Revision::EVMC_BERLIN => Fork::Berlin,
+            Revision::EVMC_LONDON => Fork::London,
+            Revision::EVMC_PARIS => Fork::Paris,
+            Revision::EVMC_SHANGHAI => Fork::Shanghai,
+            Revision::EVMC_CANCUN => Fork::Cancun,
+            // nothing from Prague is implemented yet, it runs with the Cancun rules
+            Revision::EVMC_PRAGUE => Fork::Cancun,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_revision() {
+        assert_eq!(Fork::from(Revision::EVMC_FRONTIER), Fork::Frontier);
+        assert_eq!(Fork::from(Revision::EVMC_HOMESTEAD), Fork::Homestead);
+        assert_eq!(
+            Fork::from(Revision::EVMC_TANGERINE_WHISTLE),
+            Fork::TangerineWhistle
+        );
+        assert_eq!(
+            Fork::from(Revision::EVMC_SPURIOUS_DRAGON),
+            Fork::SpuriousDragon
+        );
+        assert_eq!(Fork::from(Revision::EVMC_BYZANTIUM), Fork::Byzantium);
+        assert_eq!(
+            Fork::from(Revision::EVMC_CONSTANTINOPLE),
+            Fork::Constantinople
+        );
+        assert_eq!(Fork::from(Revision::EVMC_PETERSBURG), Fork::Petersburg);
+        assert_eq!(Fork::from(Revision::EVMC_ISTANBUL), Fork::Istanbul);
+        assert_eq!(Fork::from(Revision::EVMC_BERLIN), Fork::Berlin);
+        assert_eq!(Fork::from(Revision::EVMC_LONDON), Fork::London);
+        assert_eq!(Fork::from(Revision::EVMC_PARIS), Fork::Paris);
+        assert_eq!(Fork::from(Revision::EVMC_SHANGHAI), Fork::Shanghai);
+        assert_eq!(Fork::from(Revision::EVMC_CANCUN), Fork::Cancun);
+        assert_eq!(Fork::from(Revision::EVMC_PRAGUE), Fork::Cancun);
+        assert_eq!(Fork::from(Revision::EVMC_MAX_REVISION), Fork::Cancun);
+    }
+}
@@ -1,21 +1,522 @@
-pub const G_ZERO: u64 = 0;
-pub const G_BASE: u64 = 2;
-pub const G_VERYLOW: u64 = 3;
-pub const G_LOW: u64 = 5;
-pub const G_MID: u64 = 8;
-pub const G_HIGH: u64 = 10;
-pub const G_JUMPDEST: u64 = 1;
-pub const R_SUICIDE: isize = 24000;
-pub const G_CREATE: u64 = 32000;
-pub const G_CALLVALUE: u64 = 9000;
-pub const G_NEWACCOUNT: u64 = 25000;
-pub const G_EXP: u64 = 10;
-pub const G_MEMORY: u64 = 3;
-pub const G_LOG: u64 = 375;
-pub const G_LOGDATA: u64 = 8;
-pub const G_LOGTOPIC: u64 = 375;
-pub const G_SHA3: u64 = 30;
-pub const G_SHA3WORD: u64 = 6;
-pub const G_COPY: u64 = 3;
-pub const G_BLOCKHASH: u64 = 20;
-pub const G_CODEDEPOSIT: u64 = 200;
+use crate::fork::Fork;
+use crate::instructions::Op;
+
+use evmc_vm::{SetOptionError, StorageStatus};
+use std::convert::TryFrom;
+
+const G_ZERO: u64 = 0;
+const G_BASE: u64 = 2;
+const G_VERYLOW: u64 = 3;
+const G_LOW: u64 = 5;
+const G_MID: u64 = 8;
+const G_HIGH: u64 = 10;
+const G_JUMPDEST: u64 = 1;
+const G_SLOAD: u64 = 50;
+const G_SSET: u64 = 20000;
+const G_SRESET: u64 = 5000;
+const R_SCLEAR: isize = 15000;
+const R_SELFDESTRUCT: isize = 24000;
+const G_CREATE: u64 = 32000;
+const G_CALL: u64 = 40;
+const G_CALLVALUE: u64 = 9000;
+const G_CALLSTIPEND: u64 = 2300;
+const G_NEWACCOUNT: u64 = 25000;
+const G_EXP: u64 = 10;
+const G_EXPBYTE: u64 = 10;
+const G_MEMORY: u64 = 3;
+const G_LOG: u64 = 375;
+const G_LOGDATA: u64 = 8;
+const G_LOGTOPIC: u64 = 375;
+const G_SHA3: u64 = 30;
+const G_SHA3WORD: u64 = 6;
+const G_COPY: u64 = 3;
+const G_BALANCE: u64 = 20;
+const G_EXTCODE: u64 = 20;
+const G_BLOCKHASH: u64 = 20;
+const G_CODEDEPOSIT: u64 = 200;
+const G_WARMACCESS: u64 = 100;
+const G_COLDSLOAD: u64 = 2100;
+const G_COLDACCOUNTACCESS: u64 = 2600;
+
+/// The prices of a revision. Every instruction pays its entry in `base` before it runs, whatever
+/// depends on its operands or on the state is priced by the other fields.
+#[derive(Clone, Debug)]
+pub struct GasSchedule {
+    pub base: [u64; 256],
+    pub exp_byte: u64,
+    pub memory: u64,
+    pub memory_quad_divisor: u64,
+    pub copy: u64,
+    pub sha3_word: u64,
+    pub log_data: u64,
+    pub log_topic: u64,
+    pub sset: u64,
+    pub sreset: u64,
+    /// Whether SSTORE is priced against the value the slot had at the start of the transaction
+    /// (EIP-1283 and EIP-2200) rather than only its current one.
+    pub net_metering: bool,
+    /// The cost of a store that leaves the slot as it is, or that writes a slot already written
+    /// to by the transaction.
+    pub sstore_dirty: u64,
+    pub sclear_refund: isize,
+    pub selfdestruct_refund: isize,
+    pub warm_access: u64,
+    pub cold_sload: u64,
+    pub cold_account_access: u64,
+    pub call_value: u64,
+    pub call_stipend: u64,
+    pub new_account: u64,
+    pub code_deposit: u64,
+    pub initcode_word: u64,
+}
+
+impl GasSchedule {
+    pub fn frontier() -> Self {
+        let mut base = [G_ZERO; 256];
+
+        let tiers: &[(&[Op], u64)] = &[
+            (
+                &[
+                    Op::Address,
+                    Op::Origin,
+                    Op::Caller,
+                    Op::CallValue,
+                    Op::CalldataSize,
+                    Op::CodeSize,
+                    Op::GasPrice,
+                    Op::ReturnDataSize,
+                    Op::Coinbase,
+                    Op::Timestamp,
+                    Op::Number,
+                    Op::Difficulty,
+                    Op::GasLimit,
+                    Op::ChainId,
+                    Op::BaseFee,
+                    Op::BlobBaseFee,
+                    Op::Pop,
+                    Op::Pc,
+                    Op::MSize,
+                    Op::Gas,
+                    Op::Push0,
+                ],
+                G_BASE,
+            ),
+            (
+                &[
+                    Op::Add,
+                    Op::Sub,
+                    Op::Lt,
+                    Op::Gt,
+                    Op::Slt,
+                    Op::Sgt,
+                    Op::Eq,
+                    Op::Iszero,
+                    Op::And,
+                    Op::Or,
+                    Op::Xor,
+                    Op::Not,
+                    Op::Byte,
+                    Op::Shl,
+                    Op::Shr,
+                    Op::Sar,
+                    Op::CalldataLoad,
+                    Op::CalldataCopy,
+                    Op::CodeCopy,
+                    Op::ReturnDataCopy,
+                    Op::MLoad,
+                    Op::MStore,
+                    Op::MStore8,
+                    Op::MCopy,
+                ],
+                G_VERYLOW,
+            ),
+            (
+                &[
+                    Op::Mul,
+                    Op::Div,
+                    Op::Sdiv,
+                    Op::Mod,
+                    Op::Smod,
+                    Op::Signextend,
+                    Op::SelfBalance,
+                ],
+                G_LOW,
+            ),
+            (&[Op::Addmod, Op::Mulmod, Op::Jump], G_MID),
+            (&[Op::Jumpi], G_HIGH),
+            (&[Op::Jumpdest], G_JUMPDEST),
+            (&[Op::Exp], G_EXP),
+            (&[Op::Sha3], G_SHA3),
+            (&[Op::Balance], G_BALANCE),
+            (
+                &[Op::ExtCodeSize, Op::ExtCodeCopy, Op::ExtCodeHash],
+                G_EXTCODE,
+            ),
+            (&[Op::BlockHash], G_BLOCKHASH),
+            (&[Op::SLoad], G_SLOAD),
+            (&[Op::TLoad, Op::TStore], G_WARMACCESS),
+            (
+                &[Op::Call, Op::CallCode, Op::DelegateCall, Op::StaticCall],
+                G_CALL,
+            ),
+            (&[Op::Create, Op::Create2], G_CREATE),
+        ];
+
+        for (ops, cost) in tiers {
+            for op in ops.iter() {
+                base[*op as usize] = *cost;
+            }
+        }
+
+        // PUSH1..32, DUP1..16 and SWAP1..16
+        for cost in base[Op::Push1 as usize..=Op::Swap16 as usize].iter_mut() {
+            *cost = G_VERYLOW;
+        }
+
+        for cost in base[Op::Log0 as usize..=Op::Log4 as usize].iter_mut() {
+            *cost = G_LOG;
+        }
+
+        Self {
+            base,
+            exp_byte: G_EXPBYTE,
+            memory: G_MEMORY,
+            memory_quad_divisor: 512,
+            copy: G_COPY,
+            sha3_word: G_SHA3WORD,
+            log_data: G_LOGDATA,
+            log_topic: G_LOGTOPIC,
+            sset: G_SSET,
+            sreset: G_SRESET,
+            net_metering: false,
+            sstore_dirty: G_SRESET,
+            sclear_refund: R_SCLEAR,
+            selfdestruct_refund: R_SELFDESTRUCT,
+            warm_access: 0,
+            cold_sload: 0,
+            cold_account_access: 0,
+            call_value: G_CALLVALUE,
+            call_stipend: G_CALLSTIPEND,
+            new_account: G_NEWACCOUNT,
+            code_deposit: G_CODEDEPOSIT,
+            initcode_word: 0,
+        }
+    }
+
+    pub fn homestead() -> Self {
+        Self::frontier()
+    }
+
+    /// EIP-150
+    pub fn tangerine_whistle() -> Self {
+        let mut s = Self::homestead();
+        s.base[Op::Balance as usize] = 400;
+        s.base[Op::ExtCodeSize as usize] = 700;
+        s.base[Op::ExtCodeCopy as usize] = 700;
+        s.base[Op::ExtCodeHash as usize] = 400;
+        s.base[Op::SLoad as usize] = 200;
+        s.base[Op::SelfDestruct as usize] = 5000;
+        for op in &[Op::Call, Op::CallCode, Op::DelegateCall, Op::StaticCall] {
+            s.base[*op as usize] = 700;
+        }
+        s
+    }
+
+    /// EIP-160
+    pub fn spurious_dragon() -> Self {
+        let mut s = Self::tangerine_whistle();
+        s.exp_byte = 50;
+        s
+    }
+
+    pub fn byzantium() -> Self {
+        Self::spurious_dragon()
+    }
+
+    /// EIP-1283
+    pub fn constantinople() -> Self {
+        let mut s = Self::byzantium();
+        s.net_metering = true;
+        s.sstore_dirty = s.base[Op::SLoad as usize];
+        s
+    }
+
+    /// EIP-1283 was withdrawn
+    pub fn petersburg() -> Self {
+        Self::byzantium()
+    }
+
+    /// EIP-1884 and EIP-2200
+    pub fn istanbul() -> Self {
+        let mut s = Self::petersburg();
+        s.base[Op::Balance as usize] = 700;
+        s.base[Op::ExtCodeHash as usize] = 700;
+        s.base[Op::SLoad as usize] = 800;
+        s.net_metering = true;
+        s.sstore_dirty = 800;
+        s
+    }
+
+    /// EIP-2929, state accesses are priced as warm, a cold access is charged on top of it
+    pub fn berlin() -> Self {
+        let mut s = Self::istanbul();
+        for op in &[
+            Op::Balance,
+            Op::ExtCodeSize,
+            Op::ExtCodeCopy,
+            Op::ExtCodeHash,
+            Op::SLoad,
+            Op::Call,
+            Op::CallCode,
+            Op::DelegateCall,
+            Op::StaticCall,
+        ] {
+            s.base[*op as usize] = G_WARMACCESS;
+        }
+        s.sreset = G_SRESET - G_COLDSLOAD;
+        s.sstore_dirty = G_WARMACCESS;
+        s.warm_access = G_WARMACCESS;
+        s.cold_sload = G_COLDSLOAD;
+        s.cold_account_access = G_COLDACCOUNTACCESS;
+        s
+    }
+
+    /// EIP-3529
+    pub fn london() -> Self {
+        let mut s = Self::berlin();
+        s.sclear_refund = 4800;
+        s.selfdestruct_refund = 0;
+        s
+    }
+
+    pub fn paris() -> Self {
+        Self::london()
+    }
+
+    /// EIP-3860
+    pub fn shanghai() -> Self {
+        let mut s = Self::paris();
+        s.initcode_word = 2;
+        s
+    }
+
+    pub fn cancun() -> Self {
+        Self::shanghai()
+    }
+
+    /// Overrides a price by name, either a field such as `sset` or the base cost of an opcode
+    /// such as `base.0x55`, so that a private chain can run with its own prices.
+    pub fn set(&mut self, name: &str, value: u64) -> Result<(), SetOptionError> {
+        if let Some(op) = name.strip_prefix("base.0x") {
+            let op = u8::from_str_radix(op, 16).map_err(|_| SetOptionError::InvalidKey)?;
+            self.base[op as usize] = value;
+            return Ok(());
+        }
+
+        let refund = || isize::try_from(value).map_err(|_| SetOptionError::InvalidValue);
+
+        match name {
+            "exp_byte" => self.exp_byte = value,
+            "memory" => self.memory = value,
+            "memory_quad_divisor" if value == 0 => return Err(SetOptionError::InvalidValue),
+            "memory_quad_divisor" => self.memory_quad_divisor = value,
+            "copy" => self.copy = value,
+            "sha3_word" => self.sha3_word = value,
+            "log_data" => self.log_data = value,
+            "log_topic" => self.log_topic = value,
+            "sset" => self.sset = value,
+            "sreset" => self.sreset = value,
+            "sstore_dirty" => self.sstore_dirty = value,
+            "sclear_refund" => self.sclear_refund = refund()?,
+            "selfdestruct_refund" => self.selfdestruct_refund = refund()?,
+            "warm_access" => self.warm_access = value,
+            "cold_sload" => self.cold_sload = value,
+            "cold_account_access" => self.cold_account_access = value,
+            "call_value" => self.call_value = value,
+            "call_stipend" => self.call_stipend = value,
+            "new_account" => self.new_account = value,
+            "code_deposit" => self.code_deposit = value,
+            "initcode_word" => self.initcode_word = value,
+            _ => return Err(SetOptionError::InvalidKey),
+        }
+
+        Ok(())
+    }
+
+    /// The total cost of `words` active words of memory, `C(a) = 3a + a² / 512` on mainnet, or
+    /// `None` if it doesn't fit in a `u64`.
+    pub fn memory_cost(&self, words: u64) -> Option<u64> {
+        let linear = words.checked_mul(self.memory)?;
+        let quadratic = words.checked_mul(words)? / self.memory_quad_divisor;
+        linear.checked_add(quadratic)
+    }
+
+    /// What a cold account access costs on top of the warm price paid up front.
+    pub fn cold_account_surcharge(&self) -> u64 {
+        self.cold_account_access.saturating_sub(self.warm_access)
+    }
+
+    /// What a cold SLOAD costs on top of the warm price paid up front.
+    pub fn cold_sload_surcharge(&self) -> u64 {
+        self.cold_sload.saturating_sub(self.warm_access)
+    }
+
+    /// The cost of an SSTORE given what it did to the slot. Without net metering only the
+    /// current and new values matter, turning a zero slot into a non-zero one costs `sset` and
+    /// anything else `sreset`.
+    pub fn sstore(&self, status: StorageStatus) -> u64 {
+        match status {
+            StorageStatus::EVMC_STORAGE_ADDED => self.sset,
+            StorageStatus::EVMC_STORAGE_MODIFIED | StorageStatus::EVMC_STORAGE_DELETED => {
+                self.sreset
+            }
+            StorageStatus::EVMC_STORAGE_DELETED_ADDED
+            | StorageStatus::EVMC_STORAGE_DELETED_RESTORED
+                if !self.net_metering =>
+            {
+                self.sset
+            }
+            _ if !self.net_metering => self.sreset,
+            _ => self.sstore_dirty,
+        }
+    }
+
+    /// The refund earned by an SSTORE given what it did to the slot, negative when it takes back
+    /// a refund given by an earlier store. Without net metering only clearing a slot is refunded.
+    pub fn sstore_refund(&self, status: StorageStatus) -> isize {
+        let dirty = self.sstore_dirty as isize;
+
+        match status {
+            StorageStatus::EVMC_STORAGE_DELETED => self.sclear_refund,
+            StorageStatus::EVMC_STORAGE_MODIFIED_DELETED
+            | StorageStatus::EVMC_STORAGE_ADDED_DELETED
+                if !self.net_metering =>
+            {
+                self.sclear_refund
+            }
+            _ if !self.net_metering => 0,
+            StorageStatus::EVMC_STORAGE_DELETED_ADDED => -self.sclear_refund,
+            StorageStatus::EVMC_STORAGE_MODIFIED_DELETED => self.sclear_refund,
+            StorageStatus::EVMC_STORAGE_DELETED_RESTORED => {
+                self.sreset as isize - dirty - self.sclear_refund
+            }
+            StorageStatus::EVMC_STORAGE_ADDED_DELETED => self.sset as isize - dirty,
+            StorageStatus::EVMC_STORAGE_MODIFIED_RESTORED => self.sreset as isize - dirty,
+            _ => 0,
+        }
+    }
+}
+
+impl From<Fork> for GasSchedule {
+    fn from(fork: Fork) -> Self {
+        match fork {
+            Fork::Frontier => Self::frontier(),
+            Fork::Homestead => Self::homestead(),
+            Fork::TangerineWhistle => Self::tangerine_whistle(),
+            Fork::SpuriousDragon => Self::spurious_dragon(),
+            Fork::Byzantium => Self::byzantium(),
+            Fork::Constantinople => Self::constantinople(),
+            Fork::Petersburg => Self::petersburg(),
+            Fork::Istanbul => Self::istanbul(),
+            Fork::Berlin => Self::berlin(),
+            Fork::London => Self::london(),
+            Fork::Paris => Self::paris(),
+            Fork::Shanghai => Self::shanghai(),
+            Fork::Cancun => Self::cancun(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use evmc_vm::ffi::evmc_storage_status::*;
+
+    fn sstore(schedule: &GasSchedule, status: StorageStatus) -> (u64, isize) {
+        (schedule.sstore(status), schedule.sstore_refund(status))
+    }
+
+    #[test]
+    fn sstore_legacy() {
+        let s = GasSchedule::petersburg();
+
+        assert_eq!(sstore(&s, EVMC_STORAGE_ASSIGNED), (5000, 0));
+        assert_eq!(sstore(&s, EVMC_STORAGE_ADDED), (20000, 0));
+        assert_eq!(sstore(&s, EVMC_STORAGE_DELETED), (5000, 15000));
+        assert_eq!(sstore(&s, EVMC_STORAGE_MODIFIED), (5000, 0));
+        assert_eq!(sstore(&s, EVMC_STORAGE_DELETED_ADDED), (20000, 0));
+        assert_eq!(sstore(&s, EVMC_STORAGE_MODIFIED_DELETED), (5000, 15000));
+        assert_eq!(sstore(&s, EVMC_STORAGE_DELETED_RESTORED), (20000, 0));
+        assert_eq!(sstore(&s, EVMC_STORAGE_ADDED_DELETED), (5000, 15000));
+        assert_eq!(sstore(&s, EVMC_STORAGE_MODIFIED_RESTORED), (5000, 0));
+    }
+
+    #[test]
+    fn sstore_istanbul() {
+        let s = GasSchedule::istanbul();
+
+        assert_eq!(sstore(&s, EVMC_STORAGE_ASSIGNED), (800, 0));
+        assert_eq!(sstore(&s, EVMC_STORAGE_ADDED), (20000, 0));
+        assert_eq!(sstore(&s, EVMC_STORAGE_DELETED), (5000, 15000));
+        assert_eq!(sstore(&s, EVMC_STORAGE_MODIFIED), (5000, 0));
+        assert_eq!(sstore(&s, EVMC_STORAGE_DELETED_ADDED), (800, -15000));
+        assert_eq!(sstore(&s, EVMC_STORAGE_MODIFIED_DELETED), (800, 15000));
+        assert_eq!(
+            sstore(&s, EVMC_STORAGE_DELETED_RESTORED),
+            (800, 4200 - 15000)
+        );
+        assert_eq!(sstore(&s, EVMC_STORAGE_ADDED_DELETED), (800, 19200));
+        assert_eq!(sstore(&s, EVMC_STORAGE_MODIFIED_RESTORED), (800, 4200));
+    }
+
+    #[test]
+    fn sstore_london() {
+        let s = GasSchedule::london();
+
+        assert_eq!(sstore(&s, EVMC_STORAGE_ASSIGNED), (100, 0));
+        assert_eq!(sstore(&s, EVMC_STORAGE_ADDED), (20000, 0));
+        assert_eq!(sstore(&s, EVMC_STORAGE_DELETED), (2900, 4800));
+        assert_eq!(sstore(&s, EVMC_STORAGE_MODIFIED), (2900, 0));
+        assert_eq!(sstore(&s, EVMC_STORAGE_DELETED_ADDED), (100, -4800));
+        assert_eq!(sstore(&s, EVMC_STORAGE_MODIFIED_DELETED), (100, 4800));
+        assert_eq!(
+            sstore(&s, EVMC_STORAGE_DELETED_RESTORED),
+            (100, 2800 - 4800)
+        );
+        assert_eq!(sstore(&s, EVMC_STORAGE_ADDED_DELETED), (100, 19900));
+        assert_eq!(sstore(&s, EVMC_STORAGE_MODIFIED_RESTORED), (100, 2800));
+    }
+
+    #[test]
+    fn set_prices() {
+        let mut s = GasSchedule::london();
+
+        s.set("sset", 5000).unwrap();
+        s.set("base.0x55", 0).unwrap();
+        s.set("base.0x0A", 20).unwrap();
+
+        assert_eq!(sstore(&s, EVMC_STORAGE_ADDED), (5000, 0));
+        assert_eq!(s.base[Op::SStore as usize], 0);
+        assert_eq!(s.base[Op::Exp as usize], 20);
+    }
+
+    #[test]
+    fn set_bad_prices() {
+        let mut s = GasSchedule::london();
+
+        assert!(matches!(s.set("gas", 1), Err(SetOptionError::InvalidKey)));
+        assert!(matches!(
+            s.set("base.0x100", 1),
+            Err(SetOptionError::InvalidKey)
+        ));
+        assert!(matches!(
+            s.set("memory_quad_divisor", 0),
+            Err(SetOptionError::InvalidValue)
+        ));
+        assert!(matches!(
+            s.set("sclear_refund", u64::MAX),
+            Err(SetOptionError::InvalidValue)
+        ));
+    }
+}
@@ -1,7 +1,32 @@
-#[derive(Debug)]
-#[non_exhaustive]
-#[repr(u8)]
-pub enum Op {
+use crate::fork::Fork;
+
+use std::convert::TryFrom;
+
+// Declares `Op` along with a total decoder from raw bytes, so that every byte without a
+// matching variant is rejected rather than reinterpreted.
+macro_rules! opcodes {
+    ($($name: ident = $value: literal,)*) => {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        #[non_exhaustive]
+        #[repr(u8)]
+        pub enum Op {
+            $($name = $value,)*
+        }
+
+        impl TryFrom<u8> for Op {
+            type Error = u8;
+
+            fn try_from(byte: u8) -> Result<Self, Self::Error> {
+                match byte {
+                    $($value => Ok(Op::$name),)*
+                    _ => Err(byte),
+                }
+            }
+        }
+    };
+}
+
+opcodes! {
     Stop = 0x00,
 
     // basic arithmetic
@@ -62,6 +87,10 @@ pub enum Op {
     Number = 0x43,
     Difficulty = 0x44,
     GasLimit = 0x45,
+    ChainId = 0x46,
+    SelfBalance = 0x47,
+    BaseFee = 0x48,
+    BlobBaseFee = 0x4A,
 
     // vm operations
     Pop = 0x50,
@@ -76,6 +105,10 @@ pub enum Op {
     MSize = 0x59,
     Gas = 0x5A,
     Jumpdest = 0x5B,
+    TLoad = 0x5C,
+    TStore = 0x5D,
+    MCopy = 0x5E,
+    Push0 = 0x5F,
 
     // push
     Push1 = 0x60,
@@ -163,5 +196,24 @@ pub enum Op {
     Create2 = 0xF5,
     StaticCall = 0xFA,
     Revert = 0xFD,
+    Invalid = 0xFE,
     SelfDestruct = 0xFF,
 }
+
+impl Op {
+    /// The fork that introduced the instruction.
+    pub fn since(&self) -> Fork {
+        match self {
+            Op::DelegateCall => Fork::Homestead,
+            Op::ReturnDataSize | Op::ReturnDataCopy | Op::StaticCall | Op::Revert => {
+                Fork::Byzantium
+            }
+            Op::Shl | Op::Shr | Op::Sar | Op::ExtCodeHash | Op::Create2 => Fork::Constantinople,
+            Op::ChainId | Op::SelfBalance => Fork::Istanbul,
+            Op::BaseFee => Fork::London,
+            Op::Push0 => Fork::Shanghai,
+            Op::TLoad | Op::TStore | Op::MCopy | Op::BlobBaseFee => Fork::Cancun,
+            _ => Fork::Frontier,
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use evmc_vm::{ffi::evmc_status_code as StatusCode, ExecutionResult as Result};
+use crate::message::Message;
+
+use evmc_vm::{ffi::evmc_status_code as StatusCode, Address, ExecutionResult as Result};
 use primitive_types::{H160, U256};
 
 #[derive(Debug)]
@@ -7,13 +9,25 @@ pub enum Interrupt<Y, E> {
     Exit(E),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub enum Yield {
     // external
-    Call,
-    Create,
+    Call(Message, usize, usize),
+    Create(Message),
     Store(U256, U256),
     Load(U256),
+    TStore(U256, U256),
+    TLoad(U256),
+    Log(Vec<u8>, Vec<U256>),
+    Balance(Address),
+    ExtCodeSize(Address),
+    ExtCodeCopy(Address, usize, usize, usize),
+    ExtCodeHash(Address),
+    BlockHash(i64),
+    AccessAccount(Address),
+    AccessStorage(U256),
+    AccountExists(Address),
+    AccountBalance(Address),
 }
 
 #[derive(Debug, PartialEq)]
@@ -29,8 +43,10 @@ pub enum Exit {
     BadJump,
     BadRange,
     InvalidOp,
+    InvalidCode,
     CallOverflow,
     OutOfGas,
+    StaticModeViolation,
 
     // revert
     Revert(U256, U256),
@@ -40,30 +56,41 @@ pub enum Exit {
 }
 
 impl Exit {
-    pub fn to_result(self, gas: i64, mem: &[u8]) -> Result {
+    /// The result of the frame, the refund only counts if it succeeded and is capped by the host
+    /// once the whole transaction has run.
+    pub fn to_result(self, gas: i64, refund: i64, mem: &[u8]) -> Result {
         match self {
-            Self::Stop => Result::success(gas, None),
-            Self::Ret(offset, len) => {
-                let begin = offset.low_u64() as usize;
-                let end = begin + len.low_u64() as usize;
-
-                Result::success(gas, Some(&mem[begin..end]))
-            }
+            Self::Stop => Result::success(gas, refund, None),
+            Self::Ret(offset, len) => Result::success(gas, refund, output(mem, offset, len)),
             Self::SelfDestruct(_) => unreachable!(),
-            Self::StackUnderflow => Result::new(StatusCode::EVMC_STACK_UNDERFLOW, 0, None),
-            Self::StackOverflow => Result::new(StatusCode::EVMC_STACK_OVERFLOW, 0, None),
-            Self::BadJump => Result::new(StatusCode::EVMC_BAD_JUMP_DESTINATION, 0, None),
-            Self::BadRange => Result::new(StatusCode::EVMC_INVALID_MEMORY_ACCESS, 0, None),
-            Self::InvalidOp => Result::new(StatusCode::EVMC_INVALID_INSTRUCTION, 0, None),
-            Self::CallOverflow => Result::new(StatusCode::EVMC_CALL_DEPTH_EXCEEDED, 0, None),
-            Self::OutOfGas => Result::new(StatusCode::EVMC_OUT_OF_GAS, 0, None),
+            Self::StackUnderflow => Result::new(StatusCode::EVMC_STACK_UNDERFLOW, 0, 0, None),
+            Self::StackOverflow => Result::new(StatusCode::EVMC_STACK_OVERFLOW, 0, 0, None),
+            Self::BadJump => Result::new(StatusCode::EVMC_BAD_JUMP_DESTINATION, 0, 0, None),
+            Self::BadRange => Result::new(StatusCode::EVMC_INVALID_MEMORY_ACCESS, 0, 0, None),
+            Self::InvalidOp => Result::new(StatusCode::EVMC_INVALID_INSTRUCTION, 0, 0, None),
+            Self::InvalidCode => {
+                Result::new(StatusCode::EVMC_CONTRACT_VALIDATION_FAILURE, 0, 0, None)
+            }
+            Self::CallOverflow => Result::new(StatusCode::EVMC_CALL_DEPTH_EXCEEDED, 0, 0, None),
+            Self::OutOfGas => Result::new(StatusCode::EVMC_OUT_OF_GAS, 0, 0, None),
+            Self::StaticModeViolation => {
+                Result::new(StatusCode::EVMC_STATIC_MODE_VIOLATION, 0, 0, None)
+            }
             Self::Revert(offset, len) => {
-                let begin = offset.low_u64() as usize;
-                let end = begin + len.low_u64() as usize;
-
-                Result::new(StatusCode::EVMC_REVERT, gas, Some(&mem[begin..end]))
+                Result::new(StatusCode::EVMC_REVERT, gas, 0, output(mem, offset, len))
             }
-            Self::NotSupported => Result::new(StatusCode::EVMC_UNDEFINED_INSTRUCTION, 0, None),
+            Self::NotSupported => Result::new(StatusCode::EVMC_UNDEFINED_INSTRUCTION, 0, 0, None),
         }
     }
 }
+
+fn output(mem: &[u8], offset: U256, len: U256) -> Option<&[u8]> {
+    if len.is_zero() {
+        return None;
+    }
+
+    let begin = offset.low_u64() as usize;
+    let end = begin + len.low_u64() as usize;
+
+    Some(&mem[begin..end])
+}
@@ -1,9 +1,12 @@
 pub mod account;
+pub mod analysis;
 pub mod env;
 pub mod execute;
+pub mod fork;
 pub mod gas;
 pub mod instructions;
 pub mod interrupt;
 pub mod machine;
+pub mod memory;
 pub mod message;
 pub mod utils;
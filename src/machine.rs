@@ -1,16 +1,23 @@
+use crate::analysis::JumpDests;
 use crate::env::Environment;
-use crate::gas::*;
+use crate::fork::Fork;
+use crate::gas::GasSchedule;
 use crate::instructions::Op;
 use crate::interrupt::{Exit, Interrupt, Yield};
-use crate::message::Message;
-use crate::utils::I256;
+use crate::memory::Memory;
+use crate::message::{Kind, Message};
+use crate::utils::{Sign, I256};
 
-use log::{debug, error, trace};
+use evmc_vm::{
+    AccessStatus, Address, Bytes32, ExecutionResult, MessageFlags, StatusCode, StorageStatus,
+    Uint256,
+};
+use log::{debug, trace};
 use primitive_types::{H160, U256, U512};
 use std::cmp::min;
-use std::convert::TryInto;
-use std::mem;
+use std::convert::{TryFrom, TryInto};
 use std::ops::{BitAnd, BitOr, BitXor};
+use tiny_keccak::{Hasher, Keccak};
 
 macro_rules! pop {
     ($s: expr) => {{
@@ -21,6 +28,15 @@ macro_rules! pop {
     }};
 }
 
+macro_rules! peek {
+    ($s: expr, $n: expr) => {{
+        match $s.len().checked_sub($n + 1) {
+            Some(i) => $s[i],
+            None => return Interrupt::Exit(Exit::StackUnderflow),
+        }
+    }};
+}
+
 macro_rules! push {
     ($s: expr, $v: expr) => {{
         if $s.len() > 1023 {
@@ -48,74 +64,210 @@ macro_rules! spend_gas {
     }};
 }
 
+// turns an offset and a size from the stack into a range of memory, paying for the expansion. an
+// empty range never expands memory, wherever it points
+macro_rules! mem_range {
+    ($m: expr, $offset: expr, $size: expr) => {{
+        let offset: U256 = $offset;
+        let size: U256 = $size;
+
+        if size.is_zero() {
+            (0, 0)
+        } else if offset > MAX_MEMORY.into() || size > MAX_MEMORY.into() {
+            return Interrupt::Exit(Exit::OutOfGas);
+        } else {
+            let (offset, size) = (offset.as_usize(), size.as_usize());
+            pay_mem_gas!($m, offset, size);
+            (offset, size)
+        }
+    }};
+}
+
 macro_rules! pay_mem_gas {
     ($m: expr, $begin: expr, $size: expr) => {{
-        if $m.memory_size <= $begin + $size {
-            let mut mem_expansion = ($begin + $size) - $m.memory_size;
-
-            // round to closest word
-            if mem_expansion % 32 != 0 {
-                mem_expansion += 31
-            }
+        match $m.memory.expansion_cost($begin, $size, $m.schedule) {
+            Some(cost) => spend_gas!($m.gas, cost),
+            None => return Interrupt::Exit(Exit::OutOfGas),
+        }
 
-            mem_expansion /= 32;
+        $m.memory.expand($begin, $size);
+    }};
+}
 
-            spend_gas!(
-                $m.gas,
-                G_MEMORY * mem_expansion as u64 + mem_expansion.pow(2) as u64 / 512
-            );
+// queries ask the host about the state, the instruction is run again once it has answered so they
+// have to come before anything that changes the machine
+macro_rules! is_cold {
+    ($m: expr, $y: expr) => {{
+        match $m.access {
+            Some(status) => status == AccessStatus::EVMC_ACCESS_COLD,
+            None => {
+                $m.pc -= 1;
+                $m.resumed = true;
+                return Interrupt::Yield($y);
+            }
+        }
+    }};
+}
 
-            $m.memory_size += mem_expansion * 32;
+macro_rules! exists {
+    ($m: expr, $address: expr) => {{
+        match $m.exists {
+            Some(exists) => exists,
+            None => {
+                $m.pc -= 1;
+                $m.resumed = true;
+                return Interrupt::Yield(Yield::AccountExists($address));
+            }
+        }
+    }};
+}
 
-            // resize memory if needed
-            if $m.memory.len() < $begin + $size {
-                $m.memory.resize($begin + $size + 256, 0);
+macro_rules! balance {
+    ($m: expr, $address: expr) => {{
+        match $m.balance {
+            Some(balance) => balance,
+            None => {
+                $m.pc -= 1;
+                $m.resumed = true;
+                return Interrupt::Yield(Yield::AccountBalance($address));
             }
         }
     }};
 }
 
-macro_rules! set_mem {
-    ($m: expr, $mem_begin: expr, $data_begin: expr, $data: expr, $size: expr) => {{
-        pay_mem_gas!($m, $mem_begin, $size);
-
-        // forgive me lord, i have sinned
-        if $data.len() <= $data_begin {
-            let data = vec![0; $size];
-            $m.memory[$mem_begin..$mem_begin + $size].copy_from_slice(&data[0..$size]);
-        } else if $data.len() <= $data_begin + $size {
-            let mut data = vec![0; $size];
-            data[0..$data.len() - $data_begin].copy_from_slice(&$data[$data_begin..$data.len()]);
-            $m.memory[$mem_begin..$mem_begin + $size].copy_from_slice(&data[0..$size]);
-        } else {
-            $m.memory[$mem_begin..$mem_begin + $size]
-                .copy_from_slice(&$data[$data_begin..$data_begin + $size]);
+// EIP-214 forbids anything that changes the state inside a static call
+macro_rules! not_static {
+    ($m: expr) => {{
+        if $m.is_static {
+            return Interrupt::Exit(Exit::StaticModeViolation);
         }
     }};
 }
 
+macro_rules! pay_copy_gas {
+    ($m: expr, $size: expr) => {{
+        spend_gas!(
+            $m.gas,
+            $m.schedule.copy.saturating_mul(($size as u64).div_ceil(32))
+        );
+    }};
+}
+
+macro_rules! copy_mem {
+    ($m: expr, $mem_begin: expr, $data_begin: expr, $data: expr, $size: expr) => {{
+        let (mem_begin, size) = mem_range!($m, $mem_begin, $size);
+        pay_copy_gas!($m, size);
+
+        $m.memory.copy_from(mem_begin, $data, $data_begin, size);
+    }};
+}
+
+// no gas limit comes anywhere near paying for this much memory
+const MAX_MEMORY: usize = u32::MAX as usize;
+
+pub const MAX_CALL_DEPTH: i32 = 1024;
+
+// EIP-170 and EIP-3860 size limits
+const MAX_CODE_SIZE: usize = 0x6000;
+const MAX_INITCODE_SIZE: usize = 2 * MAX_CODE_SIZE;
+
+fn to_address(v: U256) -> Address {
+    let mut address = Address::default();
+    address.bytes.copy_from_slice(&<[u8; 32]>::from(v)[12..32]);
+    address
+}
+
 pub struct Machine<'a> {
     pub pc: usize,
     pub stack: Vec<U256>,
-    pub memory: Vec<u8>,
-    pub memory_size: usize,
+    pub memory: Memory,
+    pub return_data: Vec<u8>,
     pub code: &'a [u8],
+    pub jumpdests: &'a JumpDests,
     pub gas: u64,
+    pub refund: isize,
+    pub access: Option<AccessStatus>,
+    pub exists: Option<bool>,
+    pub balance: Option<U256>,
+    pub resumed: bool,
+    pub is_static: bool,
     pub msg: &'a Message,
     pub env: &'a Environment,
+    pub fork: Fork,
+    pub schedule: &'a GasSchedule,
 }
 
 impl<'a> Machine<'a> {
-    pub fn new(code: &'a [u8], msg: &'a Message, env: &'a Environment) -> Self {
+    pub fn new(
+        fork: Fork,
+        schedule: &'a GasSchedule,
+        code: &'a [u8],
+        jumpdests: &'a JumpDests,
+        msg: &'a Message,
+        env: &'a Environment,
+    ) -> Self {
         Self {
             pc: 0,
             stack: vec![],
-            memory: vec![0; 128],
-            memory_size: 0,
+            memory: Memory::new(),
+            return_data: vec![],
             code,
+            jumpdests,
             gas: msg.gas() as u64,
+            refund: 0,
+            access: None,
+            exists: None,
+            balance: None,
+            resumed: false,
+            is_static: msg.flags() & MessageFlags::EVMC_STATIC as u32 != 0,
             msg,
             env,
+            fork,
+            schedule,
+        }
+    }
+
+    /// Charges for an SSTORE once the host has stored the value, the storage status tells what
+    /// the store did to the slot over the course of the transaction.
+    pub fn charge_sstore(&mut self, status: StorageStatus) -> Result<(), Exit> {
+        self.gas = self
+            .gas
+            .checked_sub(self.schedule.sstore(status))
+            .ok_or(Exit::OutOfGas)?;
+
+        self.refund += self.schedule.sstore_refund(status);
+
+        Ok(())
+    }
+
+    /// Takes back what is left of the gas given to a call, along with its output which is also
+    /// copied into the memory range set aside for it.
+    pub fn return_from_call(&mut self, result: &ExecutionResult, out_begin: usize, out_len: usize) {
+        self.gas += result.gas_left() as u64;
+        if result.status_code() == StatusCode::EVMC_SUCCESS {
+            self.refund += result.gas_refund() as isize;
+        }
+
+        self.return_data = result.output().cloned().unwrap_or_default();
+
+        let len = min(out_len, self.return_data.len());
+        if len != 0 {
+            self.memory[out_begin..out_begin + len].copy_from_slice(&self.return_data[..len]);
+        }
+
+        if result.status_code() == StatusCode::EVMC_SUCCESS {
+            self.stack.push(U256::one());
+        } else {
+            self.stack.push(U256::zero());
+        }
+    }
+
+    /// The flags of the messages sent by this frame, a static frame only sends static messages.
+    fn child_flags(&self) -> u32 {
+        if self.is_static {
+            MessageFlags::EVMC_STATIC as u32
+        } else {
+            0
         }
     }
 
@@ -128,50 +280,58 @@ impl<'a> Machine<'a> {
                 self.stack,
             );
 
-            let op: Op = unsafe { mem::transmute(self.code[self.pc]) };
+            let op = match Op::try_from(self.code[self.pc]) {
+                Ok(op) => op,
+                Err(_) => return Interrupt::Exit(Exit::InvalidOp),
+            };
             self.pc += 1;
 
             debug!("{:?}", op);
 
+            if op.since() > self.fork {
+                return Interrupt::Exit(Exit::InvalidOp);
+            }
+
+            // an instruction run again with the answer to a query has paid already
+            if self.resumed {
+                self.resumed = false;
+            } else {
+                self.access = None;
+                self.exists = None;
+                self.balance = None;
+                spend_gas!(self.gas, self.schedule.base[op as usize]);
+            }
+
             match op {
                 Op::Stop => return Interrupt::Exit(Exit::Stop),
                 Op::Add => {
-                    spend_gas!(self.gas, G_VERYLOW);
                     let (r, _) = pop!(self.stack).overflowing_add(pop!(self.stack));
                     self.stack.push(r);
                 }
                 Op::Mul => {
-                    spend_gas!(self.gas, G_LOW);
                     let (r, _) = pop!(self.stack).overflowing_mul(pop!(self.stack));
                     self.stack.push(r);
                 }
                 Op::Sub => {
-                    spend_gas!(self.gas, G_VERYLOW);
                     let (r, _) = pop!(self.stack).overflowing_sub(pop!(self.stack));
                     self.stack.push(r);
                 }
-                Op::Div => {
-                    spend_gas!(self.gas, G_LOW);
-                    match pop!(self.stack).checked_div(pop!(self.stack)) {
-                        Some(r) => self.stack.push(r),
-                        None => self.stack.push(0.into()),
-                    }
-                }
+                Op::Div => match pop!(self.stack).checked_div(pop!(self.stack)) {
+                    Some(r) => self.stack.push(r),
+                    None => self.stack.push(0.into()),
+                },
                 Op::Sdiv => {
-                    spend_gas!(self.gas, G_LOW);
                     let op1: I256 = pop!(self.stack).into();
                     let op2: I256 = pop!(self.stack).into();
                     self.stack.push((op1 / op2).into())
                 }
                 Op::Mod => {
-                    spend_gas!(self.gas, G_LOW);
                     let r = pop!(self.stack)
                         .checked_rem(pop!(self.stack))
                         .unwrap_or(0.into());
                     self.stack.push(r);
                 }
                 Op::Smod => {
-                    spend_gas!(self.gas, G_LOW);
                     let op1: I256 = pop!(self.stack).into();
                     let op2: I256 = pop!(self.stack).into();
                     let r: I256 = op1.checked_rem(op2).unwrap_or(I256::zero());
@@ -179,7 +339,6 @@ impl<'a> Machine<'a> {
                     self.stack.push(r.into());
                 }
                 Op::Addmod => {
-                    spend_gas!(self.gas, G_MID);
                     let op1: U512 = pop!(self.stack).into();
                     let op2: U512 = pop!(self.stack).into();
                     let op3: U512 = pop!(self.stack).into();
@@ -193,7 +352,6 @@ impl<'a> Machine<'a> {
                     self.stack.push(r);
                 }
                 Op::Mulmod => {
-                    spend_gas!(self.gas, G_MID);
                     let op1: U512 = pop!(self.stack).into();
                     let op2: U512 = pop!(self.stack).into();
                     let op3: U512 = pop!(self.stack).into();
@@ -207,9 +365,12 @@ impl<'a> Machine<'a> {
                     self.stack.push(r);
                 }
                 Op::Exp => {
-                    // gas todo
                     let mut op1 = pop!(self.stack);
                     let mut op2 = pop!(self.stack);
+                    spend_gas!(
+                        self.gas,
+                        self.schedule.exp_byte * op2.bits().div_ceil(8) as u64
+                    );
                     let mut r: U256 = 1.into();
 
                     while op2 != 0.into() {
@@ -223,7 +384,6 @@ impl<'a> Machine<'a> {
                     self.stack.push(r);
                 }
                 Op::Signextend => {
-                    spend_gas!(self.gas, G_LOW);
                     let op1 = pop!(self.stack);
                     let op2 = pop!(self.stack);
                     let mut ret = U256::zero();
@@ -249,7 +409,6 @@ impl<'a> Machine<'a> {
                     self.stack.push(ret)
                 }
                 Op::Lt => {
-                    spend_gas!(self.gas, G_VERYLOW);
                     if pop!(self.stack).lt(&pop!(self.stack)) {
                         self.stack.push(1.into());
                     } else {
@@ -257,7 +416,6 @@ impl<'a> Machine<'a> {
                     }
                 }
                 Op::Gt => {
-                    spend_gas!(self.gas, G_VERYLOW);
                     if pop!(self.stack).gt(&pop!(self.stack)) {
                         self.stack.push(1.into());
                     } else {
@@ -265,7 +423,6 @@ impl<'a> Machine<'a> {
                     }
                 }
                 Op::Slt => {
-                    spend_gas!(self.gas, G_VERYLOW);
                     let op1: I256 = pop!(self.stack).into();
                     let op2: I256 = pop!(self.stack).into();
 
@@ -276,7 +433,6 @@ impl<'a> Machine<'a> {
                     }
                 }
                 Op::Sgt => {
-                    spend_gas!(self.gas, G_VERYLOW);
                     let op1: I256 = pop!(self.stack).into();
                     let op2: I256 = pop!(self.stack).into();
 
@@ -287,7 +443,6 @@ impl<'a> Machine<'a> {
                     }
                 }
                 Op::Eq => {
-                    spend_gas!(self.gas, G_VERYLOW);
                     if pop!(self.stack).eq(&pop!(self.stack)) {
                         self.stack.push(1.into());
                     } else {
@@ -295,7 +450,6 @@ impl<'a> Machine<'a> {
                     }
                 }
                 Op::Iszero => {
-                    spend_gas!(self.gas, G_VERYLOW);
                     if pop!(self.stack) == U256::zero() {
                         self.stack.push(1.into());
                     } else {
@@ -303,50 +457,103 @@ impl<'a> Machine<'a> {
                     }
                 }
                 Op::And => {
-                    spend_gas!(self.gas, G_VERYLOW);
                     let r = pop!(self.stack).bitand(pop!(self.stack));
                     self.stack.push(r);
                 }
                 Op::Or => {
-                    spend_gas!(self.gas, G_VERYLOW);
                     let r = pop!(self.stack).bitor(pop!(self.stack));
                     self.stack.push(r);
                 }
                 Op::Xor => {
-                    spend_gas!(self.gas, G_VERYLOW);
                     let r = pop!(self.stack).bitxor(pop!(self.stack));
                     self.stack.push(r);
                 }
                 Op::Not => {
-                    spend_gas!(self.gas, G_VERYLOW);
                     let r = !pop!(self.stack);
                     self.stack.push(r);
                 }
                 Op::Byte => {
-                    spend_gas!(self.gas, G_VERYLOW);
                     let idx = pop!(self.stack).low_u64().checked_rem(32).unwrap_or(0);
                     let op: [u8; 32] = pop!(self.stack).into();
                     self.stack.push(op[idx as usize].into());
                 }
+                Op::Shl => {
+                    let shift = pop!(self.stack);
+                    let value = pop!(self.stack);
+
+                    if shift < 256.into() {
+                        self.stack.push(value << shift.as_usize());
+                    } else {
+                        self.stack.push(U256::zero());
+                    }
+                }
+                Op::Shr => {
+                    let shift = pop!(self.stack);
+                    let value = pop!(self.stack);
+
+                    if shift < 256.into() {
+                        self.stack.push(value >> shift.as_usize());
+                    } else {
+                        self.stack.push(U256::zero());
+                    }
+                }
+                Op::Sar => {
+                    let shift = pop!(self.stack);
+                    let value: I256 = pop!(self.stack).into();
+
+                    // negative values round towards negative infinity, ending up at -1
+                    let r = match value.0 {
+                        Sign::Minus if shift < 256.into() => I256(
+                            Sign::Minus,
+                            ((value.1 - U256::one()) >> shift.as_usize()) + U256::one(),
+                        ),
+                        Sign::Minus => I256(Sign::Minus, U256::one()),
+                        _ if shift < 256.into() => I256(value.0, value.1 >> shift.as_usize()),
+                        _ => I256::zero(),
+                    };
+
+                    self.stack.push(r.into());
+                }
+                Op::Sha3 => {
+                    let (begin, len) = mem_range!(self, pop!(self.stack), pop!(self.stack));
+
+                    spend_gas!(
+                        self.gas,
+                        self.schedule
+                            .sha3_word
+                            .saturating_mul((len as u64).div_ceil(32))
+                    );
+
+                    let mut hasher = Keccak::v256();
+                    let mut hash = [0u8; 32];
+
+                    hasher.update(&self.memory[begin..begin + len]);
+
+                    hasher.finalize(&mut hash);
+                    push!(self.stack, &hash[..]);
+                }
                 Op::Address => {
-                    spend_gas!(self.gas, G_BASE);
-                    push!(self.stack, &self.msg.destination().bytes[..]);
+                    push!(self.stack, &self.msg.recipient().bytes[..]);
+                }
+                Op::Balance => {
+                    if self.fork >= Fork::Berlin
+                        && is_cold!(self, Yield::AccessAccount(to_address(peek!(self.stack, 0))))
+                    {
+                        spend_gas!(self.gas, self.schedule.cold_account_surcharge());
+                    }
+                    let address = to_address(pop!(self.stack));
+                    return Interrupt::Yield(Yield::Balance(address));
                 }
-                Op::Balance => {}
                 Op::Origin => {
-                    spend_gas!(self.gas, G_BASE);
                     push!(self.stack, &self.env.origin.to_fixed_bytes()[..]);
                 }
                 Op::Caller => {
-                    spend_gas!(self.gas, G_BASE);
                     push!(self.stack, &self.msg.sender().bytes[..]);
                 }
                 Op::CallValue => {
-                    spend_gas!(self.gas, G_BASE);
                     push!(self.stack, &self.msg.value().bytes[..]);
                 }
                 Op::CalldataLoad => {
-                    spend_gas!(self.gas, G_VERYLOW);
                     let begin = pop!(self.stack);
                     let mut ret = [0u8; 32];
 
@@ -375,146 +582,221 @@ impl<'a> Machine<'a> {
                     self.stack.push(ret.into());
                 }
                 Op::CalldataSize => {
-                    spend_gas!(self.gas, G_BASE);
                     self.stack
                         .push(self.msg.input().unwrap_or(&vec![]).len().into());
                 }
                 Op::CalldataCopy => {
-                    spend_gas!(self.gas, G_VERYLOW);
-
-                    let mem_begin = pop!(self.stack).low_u64() as usize;
-                    let data_begin = pop!(self.stack).low_u64() as usize;
-                    let len = pop!(self.stack).low_u64() as usize;
-
-                    set_mem!(
-                        self,
-                        mem_begin,
-                        data_begin,
-                        self.msg.input().unwrap_or(&vec![]).clone(),
-                        len
-                    );
+                    let mem_begin = pop!(self.stack);
+                    let data_begin = min(pop!(self.stack), usize::MAX.into()).as_usize();
+                    let len = pop!(self.stack);
+
+                    let input = self.msg.input().map(|i| &i[..]).unwrap_or_default();
+                    copy_mem!(self, mem_begin, data_begin, input, len);
                 }
                 Op::CodeSize => {
-                    spend_gas!(self.gas, G_BASE);
                     self.stack.push(self.code.len().into());
                 }
                 Op::CodeCopy => {
-                    spend_gas!(self.gas, G_VERYLOW);
+                    let mem_begin = pop!(self.stack);
+                    let code_begin = min(pop!(self.stack), usize::MAX.into()).as_usize();
+                    let len = pop!(self.stack);
 
-                    let mem_begin = pop!(self.stack).low_u64() as usize;
-                    let code_begin = pop!(self.stack).low_u64() as usize;
-                    let len = pop!(self.stack).low_u64() as usize;
+                    copy_mem!(self, mem_begin, code_begin, self.code, len);
+                }
+                Op::ExtCodeSize => {
+                    if self.fork >= Fork::Berlin
+                        && is_cold!(self, Yield::AccessAccount(to_address(peek!(self.stack, 0))))
+                    {
+                        spend_gas!(self.gas, self.schedule.cold_account_surcharge());
+                    }
+                    let address = to_address(pop!(self.stack));
+                    return Interrupt::Yield(Yield::ExtCodeSize(address));
+                }
+                Op::ExtCodeCopy => {
+                    if self.fork >= Fork::Berlin
+                        && is_cold!(self, Yield::AccessAccount(to_address(peek!(self.stack, 0))))
+                    {
+                        spend_gas!(self.gas, self.schedule.cold_account_surcharge());
+                    }
+                    let address = to_address(pop!(self.stack));
+                    let mem_begin = pop!(self.stack);
+                    let code_begin = min(pop!(self.stack), usize::MAX.into()).as_usize();
+                    let (mem_begin, len) = mem_range!(self, mem_begin, pop!(self.stack));
+
+                    pay_copy_gas!(self, len);
+
+                    // the host copies the code into the expanded memory
+                    if len != 0 {
+                        return Interrupt::Yield(Yield::ExtCodeCopy(
+                            address, mem_begin, code_begin, len,
+                        ));
+                    }
+                }
+                Op::ExtCodeHash => {
+                    if self.fork >= Fork::Berlin
+                        && is_cold!(self, Yield::AccessAccount(to_address(peek!(self.stack, 0))))
+                    {
+                        spend_gas!(self.gas, self.schedule.cold_account_surcharge());
+                    }
+                    let address = to_address(pop!(self.stack));
+                    return Interrupt::Yield(Yield::ExtCodeHash(address));
+                }
+                Op::ReturnDataSize => {
+                    push!(self.stack, self.return_data.len());
+                }
+                Op::ReturnDataCopy => {
+                    let mem_begin = pop!(self.stack);
+                    let data_begin = pop!(self.stack);
+                    let len = pop!(self.stack);
+
+                    // unlike the other copies, reading past the end of the buffer is an error
+                    match data_begin.checked_add(len) {
+                        Some(end) if end <= self.return_data.len().into() => (),
+                        _ => return Interrupt::Exit(Exit::BadRange),
+                    }
+
+                    let data_begin = data_begin.as_usize();
 
-                    set_mem!(self, mem_begin, code_begin, self.code, len);
+                    copy_mem!(self, mem_begin, data_begin, &self.return_data, len);
                 }
                 Op::GasPrice => {
-                    spend_gas!(self.gas, G_BASE);
                     self.stack.push(self.env.gas_price);
                 }
+                Op::BlockHash => {
+                    let number = pop!(self.stack);
+                    let current = self.env.block_number;
+
+                    // only the 256 most recent complete blocks are available
+                    if number < current && number >= current.saturating_sub(256.into()) {
+                        return Interrupt::Yield(Yield::BlockHash(number.as_u64() as i64));
+                    }
+
+                    self.stack.push(U256::zero());
+                }
                 Op::Coinbase => {
-                    spend_gas!(self.gas, G_BASE);
                     self.stack.push(self.env.coinbase.as_bytes().into());
                 }
                 Op::Timestamp => {
-                    spend_gas!(self.gas, G_BASE);
                     self.stack.push(self.env.timestamp);
                 }
                 Op::Number => {
-                    spend_gas!(self.gas, G_BASE);
                     self.stack.push(self.env.block_number);
                 }
                 Op::Difficulty => {
-                    spend_gas!(self.gas, G_BASE);
                     self.stack.push(self.env.difficulty);
                 }
                 Op::GasLimit => {
-                    spend_gas!(self.gas, G_BASE);
                     self.stack.push(self.env.gas_limit);
                 }
+                Op::ChainId => {
+                    self.stack.push(self.env.chain_id);
+                }
+                Op::BaseFee => {
+                    self.stack.push(self.env.base_fee);
+                }
+                Op::BlobBaseFee => {
+                    self.stack.push(self.env.blob_base_fee);
+                }
+                Op::SelfBalance => {
+                    return Interrupt::Yield(Yield::Balance(*self.msg.recipient()));
+                }
                 Op::Pop => {
-                    spend_gas!(self.gas, G_BASE);
                     let _ = pop!(self.stack);
                 }
                 Op::MLoad => {
-                    spend_gas!(self.gas, G_VERYLOW);
-                    let idx = pop!(self.stack).low_u64() as usize;
-                    let mut ret = [0; 32];
+                    let (idx, _) = mem_range!(self, pop!(self.stack), 32.into());
 
-                    if idx < self.memory.len() {
-                        let mem_len = self.memory.len();
-                        ret.copy_from_slice(&self.memory[idx..min(idx + 32, mem_len)]);
-                    }
-
-                    self.stack.push(ret.into());
+                    self.stack.push(self.memory[idx..idx + 32].into());
                 }
                 Op::MStore => {
-                    spend_gas!(self.gas, G_VERYLOW);
-
-                    let mem_begin = pop!(self.stack).as_u64() as usize;
+                    let (mem_begin, _) = mem_range!(self, pop!(self.stack), 32.into());
                     let value = pop!(self.stack);
 
-                    set_mem!(self, mem_begin, 0, <[u8; 32]>::from(value), 32);
+                    self.memory[mem_begin..mem_begin + 32]
+                        .copy_from_slice(&<[u8; 32]>::from(value));
                 }
                 Op::MStore8 => {
-                    spend_gas!(self.gas, G_VERYLOW);
-                    let mem_begin = pop!(self.stack).as_u64() as usize;
+                    let (mem_begin, _) = mem_range!(self, pop!(self.stack), 1.into());
                     let value = pop!(self.stack);
 
-                    set_mem!(self, mem_begin, 0, <[u8; 32]>::from(value)[31..32], 1);
+                    self.memory[mem_begin] = value.low_u64() as u8;
+                }
+                Op::MCopy => {
+                    let dest = pop!(self.stack);
+                    let src = pop!(self.stack);
+                    let len = pop!(self.stack);
+
+                    // memory grows to cover both ranges, which may overlap
+                    let (src, len) = mem_range!(self, src, len);
+                    let (dest, _) = mem_range!(self, dest, len.into());
+                    pay_copy_gas!(self, len);
+
+                    self.memory.copy_within(src..src + len, dest);
                 }
                 Op::SLoad => {
-                    spend_gas!(self.gas, 200);
+                    if self.fork >= Fork::Berlin
+                        && is_cold!(self, Yield::AccessStorage(peek!(self.stack, 0)))
+                    {
+                        spend_gas!(self.gas, self.schedule.cold_sload_surcharge());
+                    }
                     return Interrupt::Yield(Yield::Load(pop!(self.stack)));
                 }
                 Op::SStore => {
-                    // todo gas
+                    not_static!(self);
+
+                    // EIP-2200 forbids storing with no more than the call stipend left, the
+                    // remaining cost is charged once the host reports how the slot changed
+                    if self.fork >= Fork::Istanbul && self.gas <= self.schedule.call_stipend {
+                        return Interrupt::Exit(Exit::OutOfGas);
+                    }
+
+                    if self.fork >= Fork::Berlin
+                        && is_cold!(self, Yield::AccessStorage(peek!(self.stack, 0)))
+                    {
+                        spend_gas!(self.gas, self.schedule.cold_sload);
+                    }
+
                     return Interrupt::Yield(Yield::Store(pop!(self.stack), pop!(self.stack)));
                 }
+                Op::TLoad => {
+                    return Interrupt::Yield(Yield::TLoad(pop!(self.stack)));
+                }
+                Op::TStore => {
+                    not_static!(self);
+                    return Interrupt::Yield(Yield::TStore(pop!(self.stack), pop!(self.stack)));
+                }
                 Op::Jump => {
-                    spend_gas!(self.gas, G_MID);
-                    let dest = pop!(self.stack).low_u64() as usize;
-                    match self
-                        .code
-                        .get(dest)
-                        .and_then(|op| Some(unsafe { mem::transmute::<&u8, &Op>(op) }))
-                    {
-                        Some(Op::Jumpdest) => (),
-                        _ => return Interrupt::Exit(Exit::BadJump),
+                    let dest = pop!(self.stack);
+
+                    if !self.jumpdests.contains(dest) {
+                        return Interrupt::Exit(Exit::BadJump);
                     }
 
-                    self.pc = dest;
+                    self.pc = dest.as_usize();
                 }
                 Op::Jumpi => {
-                    spend_gas!(self.gas, G_HIGH);
-                    let dest = pop!(self.stack).low_u64() as usize;
+                    let dest = pop!(self.stack);
                     let condition = pop!(self.stack);
 
-                    if condition == U256::one() {
-                        match self
-                            .code
-                            .get(dest)
-                            .and_then(|op| Some(unsafe { mem::transmute::<&u8, &Op>(op) }))
-                        {
-                            Some(Op::Jumpdest) => (),
-                            _ => return Interrupt::Exit(Exit::BadJump),
+                    if !condition.is_zero() {
+                        if !self.jumpdests.contains(dest) {
+                            return Interrupt::Exit(Exit::BadJump);
                         }
 
-                        self.pc = dest;
+                        self.pc = dest.as_usize();
                     }
                 }
                 Op::Pc => {
-                    spend_gas!(self.gas, G_BASE);
                     self.stack.push((self.pc - 1).into());
                 }
                 Op::MSize => {
-                    spend_gas!(self.gas, G_BASE);
-                    self.stack.push(self.memory_size.into());
+                    self.stack.push(self.memory.len().into());
                 }
                 Op::Gas => {
-                    spend_gas!(self.gas, G_BASE);
                     self.stack.push(self.gas.into());
                 }
-                Op::Jumpdest => spend_gas!(self.gas, 1),
+                Op::Jumpdest => (),
+                Op::Push0 => push!(self.stack, U256::zero()),
                 Op::Push1
                 | Op::Push2
                 | Op::Push3
@@ -547,8 +829,7 @@ impl<'a> Machine<'a> {
                 | Op::Push30
                 | Op::Push31
                 | Op::Push32 => {
-                    spend_gas!(self.gas, G_VERYLOW);
-                    let base = from_base!(0x60, unsafe { mem::transmute::<Op, u8>(op) });
+                    let base = from_base!(0x60, op as u8);
                     if self.pc + base < self.code.len() {
                         let o = &self.code[self.pc..self.pc + base + 1];
                         push!(self.stack, o);
@@ -574,8 +855,7 @@ impl<'a> Machine<'a> {
                 | Op::Dup14
                 | Op::Dup15
                 | Op::Dup16 => {
-                    spend_gas!(self.gas, G_VERYLOW);
-                    let dup_idx = from_base!(0x80, unsafe { mem::transmute::<Op, u8>(op) });
+                    let dup_idx = from_base!(0x80, op as u8);
 
                     if !self.stack.is_empty() && dup_idx < self.stack.len() {
                         let idx = self.stack.len() - dup_idx - 1;
@@ -601,8 +881,7 @@ impl<'a> Machine<'a> {
                 | Op::Swap14
                 | Op::Swap15
                 | Op::Swap16 => {
-                    spend_gas!(self.gas, G_VERYLOW);
-                    let swap_idx = from_base!(0x90, unsafe { mem::transmute::<Op, u8>(op) });
+                    let swap_idx = from_base!(0x90, op as u8);
 
                     if 2 <= self.stack.len() && swap_idx < self.stack.len() - 1 {
                         let top = self.stack.len() - 1;
@@ -613,13 +892,269 @@ impl<'a> Machine<'a> {
                     }
                 }
 
+                Op::Call | Op::CallCode | Op::DelegateCall | Op::StaticCall => {
+                    if op == Op::Call && !peek!(self.stack, 2).is_zero() {
+                        not_static!(self);
+                    }
+
+                    let cold = self.fork >= Fork::Berlin
+                        && is_cold!(self, Yield::AccessAccount(to_address(peek!(self.stack, 1))));
+
+                    let transfers = match op {
+                        Op::Call | Op::CallCode => !peek!(self.stack, 2).is_zero(),
+                        _ => false,
+                    };
+
+                    // a call may bring a new account into existence, since EIP-161 only one that
+                    // is sent value does
+                    let creates = op == Op::Call
+                        && (transfers || self.fork < Fork::SpuriousDragon)
+                        && !exists!(self, to_address(peek!(self.stack, 1)));
+
+                    // the value has to be there to be sent
+                    let broke =
+                        transfers && balance!(self, *self.msg.recipient()) < peek!(self.stack, 2);
+
+                    if cold {
+                        spend_gas!(self.gas, self.schedule.cold_account_surcharge());
+                    }
+
+                    if transfers {
+                        spend_gas!(self.gas, self.schedule.call_value);
+                    }
+
+                    if creates {
+                        spend_gas!(self.gas, self.schedule.new_account);
+                    }
+
+                    let gas = pop!(self.stack);
+                    let address = to_address(pop!(self.stack));
+                    let value = match op {
+                        Op::Call | Op::CallCode => pop!(self.stack),
+                        _ => U256::zero(),
+                    };
+
+                    let (in_begin, in_len) = mem_range!(self, pop!(self.stack), pop!(self.stack));
+                    let (out_begin, out_len) = mem_range!(self, pop!(self.stack), pop!(self.stack));
+
+                    // EIP-150 holds back a 64th of what is left, before it the callee must be
+                    // given all of the requested gas
+                    let mut gas = if self.fork >= Fork::TangerineWhistle {
+                        min(gas, (self.gas - self.gas / 64).into()).as_u64()
+                    } else if gas > self.gas.into() {
+                        return Interrupt::Exit(Exit::OutOfGas);
+                    } else {
+                        gas.as_u64()
+                    };
+
+                    // a call too deep or sending more than the balance fails without the callee
+                    // ever running, and without taking the gas it would have been given
+                    if self.msg.depth() >= MAX_CALL_DEPTH || broke {
+                        self.return_data.clear();
+                        self.stack.push(U256::zero());
+                        continue;
+                    }
+
+                    spend_gas!(self.gas, gas);
+
+                    // the stipend is free to the caller, whatever is left of it is returned
+                    if transfers {
+                        gas += self.schedule.call_stipend;
+                    }
+
+                    let (kind, flags) = match op {
+                        Op::Call => (Kind::EVMC_CALL, self.child_flags()),
+                        Op::CallCode => (Kind::EVMC_CALLCODE, self.child_flags()),
+                        Op::DelegateCall => (Kind::EVMC_DELEGATECALL, self.child_flags()),
+                        _ => (Kind::EVMC_CALL, MessageFlags::EVMC_STATIC as u32),
+                    };
+
+                    // delegatecall keeps the sender and value of the current frame, while the
+                    // other kinds send from the currently executing account
+                    let (sender, value) = match op {
+                        Op::DelegateCall => (*self.msg.sender(), *self.msg.value()),
+                        _ => (
+                            *self.msg.recipient(),
+                            Uint256 {
+                                bytes: value.into(),
+                            },
+                        ),
+                    };
+
+                    // callcode and delegatecall run the target's code against the current account
+                    let recipient = match op {
+                        Op::CallCode | Op::DelegateCall => *self.msg.recipient(),
+                        _ => address,
+                    };
+
+                    let input = if in_len != 0 {
+                        Some(&self.memory[in_begin..in_begin + in_len])
+                    } else {
+                        None
+                    };
+
+                    let msg = Message::new(
+                        kind,
+                        flags,
+                        self.msg.depth() + 1,
+                        gas as i64,
+                        recipient,
+                        sender,
+                        input,
+                        value,
+                        Bytes32::default(),
+                        address,
+                    );
+
+                    return Interrupt::Yield(Yield::Call(msg, out_begin, out_len));
+                }
+                Op::Log0 | Op::Log1 | Op::Log2 | Op::Log3 | Op::Log4 => {
+                    not_static!(self);
+
+                    let (begin, len) = mem_range!(self, pop!(self.stack), pop!(self.stack));
+
+                    let n = from_base!(0xA0, op as u8);
+                    let mut topics = Vec::with_capacity(n);
+                    for _ in 0..n {
+                        topics.push(pop!(self.stack));
+                    }
+
+                    spend_gas!(self.gas, self.schedule.log_topic * n as u64);
+                    spend_gas!(self.gas, self.schedule.log_data.saturating_mul(len as u64));
+
+                    let data = self.memory[begin..begin + len].to_vec();
+
+                    return Interrupt::Yield(Yield::Log(data, topics));
+                }
+                Op::Create | Op::Create2 => {
+                    not_static!(self);
+
+                    // the endowment has to be there to be sent
+                    let broke = !peek!(self.stack, 0).is_zero()
+                        && balance!(self, *self.msg.recipient()) < peek!(self.stack, 0);
+
+                    let value = pop!(self.stack);
+                    let (begin, len) = mem_range!(self, pop!(self.stack), pop!(self.stack));
+
+                    let (kind, salt) = match op {
+                        Op::Create2 => (Kind::EVMC_CREATE2, pop!(self.stack)),
+                        _ => (Kind::EVMC_CREATE, U256::zero()),
+                    };
+
+                    if self.fork >= Fork::Shanghai && len > MAX_INITCODE_SIZE {
+                        return Interrupt::Exit(Exit::OutOfGas);
+                    }
+
+                    spend_gas!(
+                        self.gas,
+                        self.schedule.initcode_word * (len as u64).div_ceil(32)
+                    );
+
+                    // create2 hashes the initcode to derive the new address
+                    if kind == Kind::EVMC_CREATE2 {
+                        spend_gas!(
+                            self.gas,
+                            self.schedule.sha3_word * (len as u64).div_ceil(32)
+                        );
+                    }
+
+                    // like a call, a create too deep or without the endowment fails before it
+                    // is given any gas
+                    if self.msg.depth() >= MAX_CALL_DEPTH || broke {
+                        self.return_data.clear();
+                        self.stack.push(U256::zero());
+                        continue;
+                    }
+
+                    // EIP-150 holds back a 64th of what is left, before it the new account is
+                    // given all of it
+                    let gas = if self.fork >= Fork::TangerineWhistle {
+                        self.gas - self.gas / 64
+                    } else {
+                        self.gas
+                    };
+
+                    spend_gas!(self.gas, gas);
+
+                    let initcode = if len != 0 {
+                        Some(&self.memory[begin..begin + len])
+                    } else {
+                        None
+                    };
+
+                    let msg = Message::new(
+                        kind,
+                        self.child_flags(),
+                        self.msg.depth() + 1,
+                        gas as i64,
+                        Address::default(),
+                        *self.msg.recipient(),
+                        initcode,
+                        Uint256 {
+                            bytes: value.into(),
+                        },
+                        Bytes32 { bytes: salt.into() },
+                        Address::default(),
+                    );
+
+                    return Interrupt::Yield(Yield::Create(msg));
+                }
                 Op::Return => {
-                    return Interrupt::Exit(Exit::Ret(pop!(self.stack), pop!(self.stack)))
+                    let (begin, len) = mem_range!(self, pop!(self.stack), pop!(self.stack));
+
+                    // the returned code of a create frame becomes the new account's code
+                    match self.msg.kind() {
+                        Kind::EVMC_CREATE | Kind::EVMC_CREATE2 => {
+                            if self.fork >= Fork::SpuriousDragon && len > MAX_CODE_SIZE {
+                                return Interrupt::Exit(Exit::OutOfGas);
+                            }
+
+                            // EIP-3541 reserves the 0xEF prefix
+                            if self.fork >= Fork::London && len != 0 && self.memory[begin] == 0xEF {
+                                return Interrupt::Exit(Exit::InvalidCode);
+                            }
+
+                            let cost = self.schedule.code_deposit.saturating_mul(len as u64);
+
+                            // before homestead, a create that can't pay for its code deposit
+                            // succeeds with empty code
+                            if self.fork == Fork::Frontier && cost > self.gas {
+                                return Interrupt::Exit(Exit::Ret(U256::zero(), U256::zero()));
+                            }
+
+                            spend_gas!(self.gas, cost);
+                        }
+                        _ => (),
+                    }
+
+                    return Interrupt::Exit(Exit::Ret(begin.into(), len.into()));
                 }
                 Op::Revert => {
-                    return Interrupt::Exit(Exit::Revert(pop!(self.stack), pop!(self.stack)))
+                    let (begin, len) = mem_range!(self, pop!(self.stack), pop!(self.stack));
+
+                    return Interrupt::Exit(Exit::Revert(begin.into(), len.into()));
                 }
+                Op::Invalid => return Interrupt::Exit(Exit::InvalidOp),
                 Op::SelfDestruct => {
+                    not_static!(self);
+
+                    let cold = self.fork >= Fork::Berlin
+                        && is_cold!(self, Yield::AccessAccount(to_address(peek!(self.stack, 0))));
+
+                    // EIP-150 charges for sending the funds to a new account, since EIP-161 only
+                    // when there are funds to send and the beneficiary is empty
+                    let creates = self.fork >= Fork::TangerineWhistle
+                        && (self.fork < Fork::SpuriousDragon
+                            || !balance!(self, *self.msg.recipient()).is_zero())
+                        && !exists!(self, to_address(peek!(self.stack, 0)));
+
+                    if cold {
+                        spend_gas!(self.gas, self.schedule.cold_account_access);
+                    }
+
+                    if creates {
+                        spend_gas!(self.gas, self.schedule.new_account);
+                    }
                     let val = pop!(self.stack);
 
                     // bad
@@ -630,13 +1165,1121 @@ impl<'a> Machine<'a> {
 
                     return Interrupt::Exit(Exit::SelfDestruct(H160::from(&address)));
                 }
-                _ => {
-                    error!("UNSUPPORTED OP: {:?}", op);
-                    return Interrupt::Exit(Exit::NotSupported);
-                }
             }
         }
 
         Interrupt::Exit(Exit::Ret(0.into(), 0.into()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAS: i64 = 1_000_000;
+
+    // everything a machine borrows, so that tests can build one in a line
+    struct Frame {
+        fork: Fork,
+        schedule: GasSchedule,
+        code: Vec<u8>,
+        jumpdests: JumpDests,
+        msg: Message,
+        env: Environment,
+    }
+
+    impl Frame {
+        fn new(fork: Fork, code: &[u8]) -> Self {
+            Self {
+                fork,
+                schedule: GasSchedule::from(fork),
+                code: code.to_vec(),
+                jumpdests: JumpDests::analyze(code),
+                msg: message(0),
+                env: Environment::default(),
+            }
+        }
+
+        fn machine(&self) -> Machine<'_> {
+            Machine::new(
+                self.fork,
+                &self.schedule,
+                &self.code,
+                &self.jumpdests,
+                &self.msg,
+                &self.env,
+            )
+        }
+    }
+
+    fn message(flags: u32) -> Message {
+        Message::new(
+            Kind::EVMC_CALL,
+            flags,
+            0,
+            GAS,
+            Address::default(),
+            Address::default(),
+            None,
+            Uint256::default(),
+            Bytes32::default(),
+            Address::default(),
+        )
+    }
+
+    fn exit(i: Interrupt<Yield, Exit>) -> Exit {
+        match i {
+            Interrupt::Exit(e) => e,
+            Interrupt::Yield(y) => panic!("unexpected yield {:?}", y),
+        }
+    }
+
+    fn gas_used(m: &Machine) -> u64 {
+        GAS as u64 - m.gas
+    }
+
+    #[test]
+    fn sha3_empty() {
+        // SHA3(0, 0)
+        let frame = Frame::new(Fork::London, &[0x60, 0x00, 0x60, 0x00, 0x20]);
+        let mut m = frame.machine();
+
+        assert_eq!(exit(m.run()), Exit::Ret(0.into(), 0.into()));
+        assert_eq!(
+            m.stack,
+            vec![U256::from(
+                "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+            )]
+        );
+        assert_eq!(gas_used(&m), 3 + 3 + 30);
+        assert_eq!(m.memory.len(), 0);
+    }
+
+    #[test]
+    fn sha3_empty_anywhere() {
+        // SHA3(2^255, 0) doesn't touch memory
+        let mut code = vec![0x60, 0x00, 0x7F, 0x80];
+        code.extend_from_slice(&[0; 31]);
+        code.push(0x20);
+
+        let frame = Frame::new(Fork::London, &code);
+        let mut m = frame.machine();
+
+        assert_eq!(exit(m.run()), Exit::Ret(0.into(), 0.into()));
+        assert_eq!(
+            m.stack,
+            vec![U256::from(
+                "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+            )]
+        );
+        assert_eq!(gas_used(&m), 3 + 3 + 30);
+        assert_eq!(m.memory.len(), 0);
+    }
+
+    #[test]
+    fn sha3_two_words() {
+        // SHA3(0, 64) over zeroed memory
+        let frame = Frame::new(Fork::London, &[0x60, 0x40, 0x60, 0x00, 0x20]);
+        let mut m = frame.machine();
+
+        assert_eq!(exit(m.run()), Exit::Ret(0.into(), 0.into()));
+        assert_eq!(
+            m.stack,
+            vec![U256::from(
+                "ad3228b676f7d3cd4284a5443f17f1962b36e491b30a40b2405849e597ba5fb5"
+            )]
+        );
+        // two words hashed and two words of memory
+        assert_eq!(gas_used(&m), 3 + 3 + 30 + 2 * 6 + 2 * 3);
+        assert_eq!(m.memory.len(), 64);
+    }
+
+    #[test]
+    fn sha3_partial_word() {
+        // MSTORE8(32, 0xFF), SHA3(0, 33)
+        let frame = Frame::new(
+            Fork::London,
+            &[0x60, 0xFF, 0x60, 0x20, 0x53, 0x60, 0x21, 0x60, 0x00, 0x20],
+        );
+        let mut m = frame.machine();
+
+        let mut data = [0u8; 33];
+        data[32] = 0xFF;
+        let mut hash = [0u8; 32];
+        let mut hasher = Keccak::v256();
+        hasher.update(&data);
+        hasher.finalize(&mut hash);
+
+        assert_eq!(exit(m.run()), Exit::Ret(0.into(), 0.into()));
+        assert_eq!(m.stack, vec![U256::from(&hash[..])]);
+        // the mstore8 pays for the two words of memory, the hash rounds 33 bytes up to two words
+        assert_eq!(gas_used(&m), 3 + 3 + 3 + 2 * 3 + 3 + 3 + 30 + 2 * 6);
+    }
+
+    #[test]
+    fn sha3_memory_expansion() {
+        // SHA3(32, 32) pays for two words of memory, hashing them again pays nothing more
+        let code = [0x60, 0x20, 0x80, 0x20, 0x60, 0x20, 0x80, 0x20];
+        let frame = Frame::new(Fork::London, &code[..4]);
+        let mut m = frame.machine();
+
+        assert_eq!(exit(m.run()), Exit::Ret(0.into(), 0.into()));
+        assert_eq!(gas_used(&m), 3 + 3 + 30 + 6 + 2 * 3);
+        assert_eq!(m.memory.len(), 64);
+
+        let frame = Frame::new(Fork::London, &code);
+        let mut m = frame.machine();
+
+        assert_eq!(exit(m.run()), Exit::Ret(0.into(), 0.into()));
+        assert_eq!(gas_used(&m), 2 * (3 + 3 + 30 + 6) + 2 * 3);
+    }
+
+    #[test]
+    fn sha3_out_of_gas() {
+        // SHA3(0, 2^32) can't pay for its memory
+        let frame = Frame::new(
+            Fork::London,
+            &[0x64, 0x01, 0x00, 0x00, 0x00, 0x00, 0x60, 0x00, 0x20],
+        );
+        let mut m = frame.machine();
+
+        assert_eq!(exit(m.run()), Exit::OutOfGas);
+    }
+
+    // LOGn(0, 2) with topics 1..=n over memory holding 0xAABB
+    fn log_code(n: u8) -> Vec<u8> {
+        let mut code = vec![0x61, 0xAA, 0xBB, 0x60, 0x00, 0x52];
+        for t in (1..=n).rev() {
+            code.extend_from_slice(&[0x60, t]);
+        }
+        code.extend_from_slice(&[0x60, 0x02, 0x60, 0x1E, 0xA0 + n]);
+        code
+    }
+
+    #[test]
+    fn log_topics_in_order() {
+        for n in 0..=4 {
+            let frame = Frame::new(Fork::London, &log_code(n));
+            let mut m = frame.machine();
+
+            match m.run() {
+                Interrupt::Yield(Yield::Log(data, topics)) => {
+                    assert_eq!(data, vec![0xAA, 0xBB]);
+                    assert_eq!(topics, (1..=n).map(U256::from).collect::<Vec<_>>());
+                }
+                i => panic!("unexpected interrupt {:?}", i),
+            }
+
+            // mstore and its word of memory, the pushes, then the log itself
+            let setup = 3 + 3 + 3 + 3 + 3 * n as u64 + 3 + 3;
+            assert_eq!(gas_used(&m), setup + 375 + 375 * n as u64 + 8 * 2);
+            assert!(m.stack.is_empty());
+
+            assert_eq!(exit(m.run()), Exit::Ret(0.into(), 0.into()));
+        }
+    }
+
+    #[test]
+    fn log_memory_expansion() {
+        // LOG0(32, 33) pays for the data and two more words of memory
+        let frame = Frame::new(Fork::London, &[0x60, 0x21, 0x60, 0x20, 0xA0]);
+        let mut m = frame.machine();
+
+        match m.run() {
+            Interrupt::Yield(Yield::Log(data, topics)) => {
+                assert_eq!(data, vec![0; 33]);
+                assert!(topics.is_empty());
+            }
+            i => panic!("unexpected interrupt {:?}", i),
+        }
+
+        assert_eq!(gas_used(&m), 3 + 3 + 375 + 8 * 33 + 3 * 3);
+        assert_eq!(m.memory.len(), 96);
+    }
+
+    #[test]
+    fn log_static() {
+        for n in 0..=4 {
+            let mut frame = Frame::new(Fork::London, &log_code(n));
+            frame.msg = message(MessageFlags::EVMC_STATIC as u32);
+            let mut m = frame.machine();
+
+            assert_eq!(exit(m.run()), Exit::StaticModeViolation);
+        }
+    }
+
+    #[test]
+    fn log_stack_underflow() {
+        // LOG2 with a single topic on the stack
+        let frame = Frame::new(Fork::London, &[0x60, 0x01, 0x60, 0x00, 0x60, 0x00, 0xA2]);
+        let mut m = frame.machine();
+
+        assert_eq!(exit(m.run()), Exit::StackUnderflow);
+    }
+
+    // the frame of an account 0xAA.. called by 0xBB.. with 7 wei, some way down the call stack
+    fn caller() -> Message {
+        Message::new(
+            Kind::EVMC_CALL,
+            0,
+            3,
+            GAS,
+            Address { bytes: [0xAA; 20] },
+            Address { bytes: [0xBB; 20] },
+            None,
+            Uint256 {
+                bytes: U256::from(7).into(),
+            },
+            Bytes32::default(),
+            Address { bytes: [0xAA; 20] },
+        )
+    }
+
+    // runs the machine up to the first interrupt that isn't a query, answering queries with the
+    // given state
+    fn run_with(
+        m: &mut Machine,
+        access: AccessStatus,
+        exists: bool,
+        balance: U256,
+    ) -> Interrupt<Yield, Exit> {
+        loop {
+            match m.run() {
+                Interrupt::Yield(Yield::AccessAccount(_)) => m.access = Some(access),
+                Interrupt::Yield(Yield::AccessStorage(_)) => m.access = Some(access),
+                Interrupt::Yield(Yield::AccountExists(_)) => m.exists = Some(exists),
+                Interrupt::Yield(Yield::AccountBalance(_)) => m.balance = Some(balance),
+                i => return i,
+            }
+        }
+    }
+
+    // `op` to address `to` with `gas`, and `value` for the kinds that take one, without any input
+    // or output
+    fn call(op: Op, gas: u16, to: u8, value: u8) -> Vec<u8> {
+        let mut code = vec![0x60, 0x00, 0x80, 0x80, 0x80];
+        if op == Op::Call || op == Op::CallCode {
+            code.extend_from_slice(&[0x60, value]);
+        }
+        code.extend_from_slice(&[0x60, to, 0x61]);
+        code.extend_from_slice(&gas.to_be_bytes());
+        code.push(op as u8);
+        code
+    }
+
+    fn called(i: Interrupt<Yield, Exit>) -> Message {
+        match i {
+            Interrupt::Yield(Yield::Call(child, _, _)) => child,
+            i => panic!("unexpected interrupt {:?}", i),
+        }
+    }
+
+    #[test]
+    fn call_message() {
+        let mut frame = Frame::new(Fork::Istanbul, &call(Op::Call, 0x10, 0x01, 5));
+        frame.msg = caller();
+        let mut m = frame.machine();
+
+        let child = called(run_with(
+            &mut m,
+            AccessStatus::EVMC_ACCESS_WARM,
+            true,
+            5.into(),
+        ));
+
+        assert_eq!(child.kind(), Kind::EVMC_CALL);
+        assert_eq!(child.flags(), 0);
+        assert_eq!(child.depth(), 4);
+        assert_eq!(child.gas(), 0x10 + 2300);
+        assert_eq!(*child.recipient(), to_address(1.into()));
+        assert_eq!(*child.sender(), Address { bytes: [0xAA; 20] });
+        assert_eq!(U256::from(&child.value().bytes[..]), 5.into());
+        assert_eq!(*child.code_address(), to_address(1.into()));
+    }
+
+    #[test]
+    fn callcode_message() {
+        let mut frame = Frame::new(Fork::Istanbul, &call(Op::CallCode, 0x10, 0x01, 5));
+        frame.msg = caller();
+        let mut m = frame.machine();
+
+        let child = called(run_with(
+            &mut m,
+            AccessStatus::EVMC_ACCESS_WARM,
+            true,
+            5.into(),
+        ));
+
+        assert_eq!(child.kind(), Kind::EVMC_CALLCODE);
+        assert_eq!(child.depth(), 4);
+        assert_eq!(*child.recipient(), Address { bytes: [0xAA; 20] });
+        assert_eq!(*child.sender(), Address { bytes: [0xAA; 20] });
+        assert_eq!(U256::from(&child.value().bytes[..]), 5.into());
+        assert_eq!(*child.code_address(), to_address(1.into()));
+    }
+
+    #[test]
+    fn delegatecall_message() {
+        let mut frame = Frame::new(Fork::Istanbul, &call(Op::DelegateCall, 0x10, 0x01, 0));
+        frame.msg = caller();
+        let mut m = frame.machine();
+
+        let child = called(m.run());
+
+        assert_eq!(child.kind(), Kind::EVMC_DELEGATECALL);
+        assert_eq!(child.flags(), 0);
+        assert_eq!(child.depth(), 4);
+        assert_eq!(child.gas(), 0x10);
+        assert_eq!(*child.recipient(), Address { bytes: [0xAA; 20] });
+        assert_eq!(*child.sender(), Address { bytes: [0xBB; 20] });
+        assert_eq!(U256::from(&child.value().bytes[..]), 7.into());
+        assert_eq!(*child.code_address(), to_address(1.into()));
+    }
+
+    #[test]
+    fn staticcall_message() {
+        let mut frame = Frame::new(Fork::Istanbul, &call(Op::StaticCall, 0x10, 0x01, 0));
+        frame.msg = caller();
+        let mut m = frame.machine();
+
+        let child = called(m.run());
+
+        assert_eq!(child.kind(), Kind::EVMC_CALL);
+        assert_eq!(child.flags(), MessageFlags::EVMC_STATIC as u32);
+        assert_eq!(child.depth(), 4);
+        assert_eq!(child.gas(), 0x10);
+        assert_eq!(*child.recipient(), to_address(1.into()));
+        assert_eq!(*child.sender(), Address { bytes: [0xAA; 20] });
+        assert!(child.value().bytes.iter().all(|b| *b == 0));
+        assert_eq!(*child.code_address(), to_address(1.into()));
+    }
+
+    #[test]
+    fn call_from_static_frame() {
+        let mut frame = Frame::new(Fork::Istanbul, &call(Op::Call, 0x10, 0x01, 0));
+        frame.msg = message(MessageFlags::EVMC_STATIC as u32);
+        let mut m = frame.machine();
+
+        let child = called(run_with(
+            &mut m,
+            AccessStatus::EVMC_ACCESS_WARM,
+            true,
+            0.into(),
+        ));
+
+        assert_eq!(child.flags(), MessageFlags::EVMC_STATIC as u32);
+
+        // sending value from one is not allowed
+        let mut frame = Frame::new(Fork::Istanbul, &call(Op::Call, 0x10, 0x01, 5));
+        frame.msg = message(MessageFlags::EVMC_STATIC as u32);
+        let mut m = frame.machine();
+
+        assert_eq!(exit(m.run()), Exit::StaticModeViolation);
+    }
+
+    #[test]
+    fn call_without_balance() {
+        for op in &[Op::Call, Op::CallCode] {
+            let frame = Frame::new(Fork::Istanbul, &call(*op, 0x10, 0x01, 5));
+            let mut m = frame.machine();
+            m.return_data = vec![1, 2, 3];
+
+            let i = run_with(&mut m, AccessStatus::EVMC_ACCESS_WARM, true, 4.into());
+
+            assert_eq!(exit(i), Exit::Ret(0.into(), 0.into()));
+            assert_eq!(m.stack, vec![U256::zero()]);
+            assert!(m.return_data.is_empty());
+            // the gas that would have gone to the callee stays with the caller
+            assert_eq!(gas_used(&m), 7 * 3 + 700 + 9000);
+        }
+    }
+
+    #[test]
+    fn return_from_call() {
+        let frame = Frame::new(Fork::Istanbul, &[]);
+        let mut m = frame.machine();
+        m.memory.expand(0, 32);
+        m.gas = 0;
+
+        // the output is cut to the range set aside for it
+        let result = ExecutionResult::success(100, 5, Some(&[1, 2, 3, 4]));
+        m.return_from_call(&result, 8, 2);
+
+        assert_eq!(&m.memory[6..12], &[0, 0, 1, 2, 0, 0]);
+        assert_eq!(m.return_data, vec![1, 2, 3, 4]);
+        assert_eq!(m.stack, vec![U256::one()]);
+        assert_eq!(m.gas, 100);
+        assert_eq!(m.refund, 5);
+
+        // and only covers as much of it as there is output
+        let result = ExecutionResult::revert(50, Some(&[9]));
+        m.return_from_call(&result, 16, 4);
+
+        assert_eq!(&m.memory[16..20], &[9, 0, 0, 0]);
+        assert_eq!(m.return_data, vec![9]);
+        assert_eq!(m.stack, vec![U256::one(), U256::zero()]);
+        assert_eq!(m.gas, 150);
+        assert_eq!(m.refund, 5);
+    }
+
+    // CREATE(0, 0, 0)
+    const CREATE: [u8; 7] = [0x60, 0x00, 0x80, 0x80, 0xF0, 0x00, 0x00];
+
+    #[test]
+    fn create_forwards_all_gas() {
+        let frame = Frame::new(Fork::Frontier, &CREATE);
+        let mut m = frame.machine();
+
+        match m.run() {
+            Interrupt::Yield(Yield::Create(child)) => {
+                assert_eq!(child.gas(), GAS - 3 - 3 - 3 - 32000);
+            }
+            i => panic!("unexpected interrupt {:?}", i),
+        }
+
+        assert_eq!(m.gas, 0);
+    }
+
+    #[test]
+    fn create_keeps_a_64th() {
+        for fork in &[Fork::TangerineWhistle, Fork::London, Fork::Cancun] {
+            let frame = Frame::new(*fork, &CREATE);
+            let mut m = frame.machine();
+
+            let left = GAS as u64 - 3 - 3 - 3 - 32000;
+
+            match m.run() {
+                Interrupt::Yield(Yield::Create(child)) => {
+                    assert_eq!(child.gas() as u64, left - left / 64);
+                }
+                i => panic!("unexpected interrupt {:?}", i),
+            }
+
+            assert_eq!(m.gas, left / 64);
+        }
+    }
+
+    #[test]
+    fn create2_keeps_a_64th() {
+        // CREATE2(0, 0, 0, 0)
+        let frame = Frame::new(Fork::London, &[0x60, 0x00, 0x80, 0x80, 0x80, 0xF5]);
+        let mut m = frame.machine();
+
+        let left = GAS as u64 - 3 - 3 - 3 - 3 - 32000;
+
+        match m.run() {
+            Interrupt::Yield(Yield::Create(child)) => {
+                assert_eq!(child.kind(), Kind::EVMC_CREATE2);
+                assert_eq!(child.gas() as u64, left - left / 64);
+            }
+            i => panic!("unexpected interrupt {:?}", i),
+        }
+
+        assert_eq!(m.gas, left / 64);
+    }
+
+    #[test]
+    fn call_keeps_a_64th() {
+        let frame = Frame::new(Fork::Istanbul, &call(Op::Call, 0xFFFF, 0x01, 0));
+        let mut m = frame.machine();
+        m.gas = 20000;
+
+        let left = 20000 - 7 * 3 - 700;
+
+        // more is asked for than there is, the callee gets all but a 64th
+        let child = called(m.run());
+
+        assert_eq!(child.gas() as u64, left - left / 64);
+        assert_eq!(m.gas, left / 64);
+    }
+
+    #[test]
+    fn call_before_tangerine_whistle() {
+        let frame = Frame::new(Fork::Homestead, &call(Op::Call, 0xFFFF, 0x01, 0));
+        let mut m = frame.machine();
+        m.gas = 20000;
+
+        let i = run_with(&mut m, AccessStatus::EVMC_ACCESS_WARM, true, 0.into());
+
+        assert_eq!(exit(i), Exit::OutOfGas);
+
+        // what is asked for is handed over whole
+        let frame = Frame::new(Fork::Homestead, &call(Op::Call, 0x1000, 0x01, 0));
+        let mut m = frame.machine();
+        m.gas = 20000;
+
+        let child = called(run_with(
+            &mut m,
+            AccessStatus::EVMC_ACCESS_WARM,
+            true,
+            0.into(),
+        ));
+
+        assert_eq!(child.gas(), 0x1000);
+        assert_eq!(m.gas, 20000 - 7 * 3 - 40 - 0x1000);
+    }
+
+    #[test]
+    fn call_stipend() {
+        for op in &[Op::Call, Op::CallCode] {
+            let frame = Frame::new(Fork::Istanbul, &call(*op, 0, 0x01, 5));
+            let mut m = frame.machine();
+
+            let child = called(run_with(
+                &mut m,
+                AccessStatus::EVMC_ACCESS_WARM,
+                true,
+                5.into(),
+            ));
+
+            // the stipend is given on top of the requested gas and isn't paid for
+            assert_eq!(child.gas(), 2300);
+            assert_eq!(gas_used(&m), 7 * 3 + 700 + 9000);
+        }
+    }
+
+    #[test]
+    fn call_new_account() {
+        // since EIP-161 only sending value to an account that doesn't exist creates it
+        for (fork, value, new_account) in &[
+            (Fork::Homestead, 0, 25000),
+            (Fork::Homestead, 5, 25000),
+            (Fork::Istanbul, 0, 0),
+            (Fork::Istanbul, 5, 25000),
+        ] {
+            let frame = Frame::new(*fork, &call(Op::Call, 0, 0x01, *value));
+            let mut m = frame.machine();
+
+            called(run_with(
+                &mut m,
+                AccessStatus::EVMC_ACCESS_WARM,
+                false,
+                5.into(),
+            ));
+
+            let base = if *fork == Fork::Homestead { 40 } else { 700 };
+            let call_value = if *value != 0 { 9000 } else { 0 };
+
+            assert_eq!(gas_used(&m), 7 * 3 + base + call_value + new_account);
+        }
+
+        // callcode sends the value to the current account, which exists
+        let frame = Frame::new(Fork::Istanbul, &call(Op::CallCode, 0, 0x01, 5));
+        let mut m = frame.machine();
+
+        called(run_with(
+            &mut m,
+            AccessStatus::EVMC_ACCESS_WARM,
+            false,
+            5.into(),
+        ));
+
+        assert_eq!(gas_used(&m), 7 * 3 + 700 + 9000);
+    }
+
+    // the frame at the bottom of the deepest call stack
+    fn deepest() -> Message {
+        Message::new(
+            Kind::EVMC_CALL,
+            0,
+            MAX_CALL_DEPTH,
+            GAS,
+            Address::default(),
+            Address::default(),
+            None,
+            Uint256::default(),
+            Bytes32::default(),
+            Address::default(),
+        )
+    }
+
+    #[test]
+    fn call_depth_limit() {
+        for (code, used, end) in &[
+            (
+                call(Op::Call, 0x10, 0x01, 0),
+                7 * 3 + 700,
+                Exit::Ret(0.into(), 0.into()),
+            ),
+            (CREATE.to_vec(), 3 * 3 + 32000, Exit::Stop),
+        ] {
+            let mut frame = Frame::new(Fork::Istanbul, code);
+            frame.msg = deepest();
+            let mut m = frame.machine();
+            m.return_data = vec![1, 2, 3];
+
+            // nothing is sent, the gas that would have been is kept
+            assert_eq!(exit(m.run()), *end);
+            assert_eq!(m.stack, vec![U256::zero()]);
+            assert!(m.return_data.is_empty());
+            assert_eq!(gas_used(&m), *used);
+        }
+    }
+
+    #[test]
+    fn create_without_balance() {
+        // CREATE(5, 0, 0), CREATE2(5, 0, 0, 0)
+        for (code, pushes) in &[
+            (vec![0x60, 0x00, 0x80, 0x60, 0x05, 0xF0], 3),
+            (vec![0x60, 0x00, 0x80, 0x80, 0x60, 0x05, 0xF5], 4),
+        ] {
+            let frame = Frame::new(Fork::London, code);
+            let mut m = frame.machine();
+            m.return_data = vec![1, 2, 3];
+
+            let i = run_with(&mut m, AccessStatus::EVMC_ACCESS_WARM, true, 4.into());
+
+            assert_eq!(exit(i), Exit::Ret(0.into(), 0.into()));
+            assert_eq!(m.stack, vec![U256::zero()]);
+            assert!(m.return_data.is_empty());
+            // only the create itself is paid for
+            assert_eq!(gas_used(&m), 3 * pushes + 32000);
+        }
+
+        // with just enough the endowment goes out
+        let frame = Frame::new(Fork::London, &[0x60, 0x00, 0x80, 0x60, 0x05, 0xF0]);
+        let mut m = frame.machine();
+
+        match run_with(&mut m, AccessStatus::EVMC_ACCESS_WARM, true, 5.into()) {
+            Interrupt::Yield(Yield::Create(child)) => {
+                assert_eq!(U256::from(&child.value().bytes[..]), 5.into());
+            }
+            i => panic!("unexpected interrupt {:?}", i),
+        }
+    }
+
+    // SELFDESTRUCT(1)
+    const SELFDESTRUCT: [u8; 3] = [0x60, 0x01, 0xFF];
+
+    #[test]
+    fn selfdestruct_before_tangerine_whistle() {
+        let frame = Frame::new(Fork::Homestead, &SELFDESTRUCT);
+        let mut m = frame.machine();
+
+        // nothing is asked of the host, a new beneficiary costs nothing
+        assert!(matches!(exit(m.run()), Exit::SelfDestruct(_)));
+        assert_eq!(gas_used(&m), 3);
+    }
+
+    #[test]
+    fn selfdestruct_to_new_account() {
+        for (exists, new_account) in &[(true, 0), (false, 25000)] {
+            let frame = Frame::new(Fork::TangerineWhistle, &SELFDESTRUCT);
+            let mut m = frame.machine();
+
+            // the balance doesn't matter yet
+            let i = run_with(&mut m, AccessStatus::EVMC_ACCESS_WARM, *exists, 0.into());
+
+            assert!(matches!(exit(i), Exit::SelfDestruct(_)));
+            assert_eq!(gas_used(&m), 3 + 5000 + new_account);
+        }
+    }
+
+    #[test]
+    fn selfdestruct_to_empty_account() {
+        for (exists, balance, new_account) in &[(true, 1, 0), (false, 0, 0), (false, 1, 25000)] {
+            let frame = Frame::new(Fork::SpuriousDragon, &SELFDESTRUCT);
+            let mut m = frame.machine();
+
+            let i = run_with(
+                &mut m,
+                AccessStatus::EVMC_ACCESS_WARM,
+                *exists,
+                (*balance).into(),
+            );
+
+            assert!(matches!(exit(i), Exit::SelfDestruct(_)));
+            assert_eq!(gas_used(&m), 3 + 5000 + new_account);
+        }
+    }
+
+    #[test]
+    fn balance_access() {
+        for (access, cold) in &[
+            (AccessStatus::EVMC_ACCESS_WARM, 0),
+            (AccessStatus::EVMC_ACCESS_COLD, 2500),
+        ] {
+            let frame = Frame::new(Fork::Berlin, &[0x60, 0x01, 0x31]);
+            let mut m = frame.machine();
+
+            match m.run() {
+                Interrupt::Yield(Yield::AccessAccount(a)) => assert_eq!(a, to_address(1.into())),
+                i => panic!("unexpected interrupt {:?}", i),
+            }
+            assert_eq!(gas_used(&m), 3 + 100);
+
+            m.access = Some(*access);
+            match m.run() {
+                Interrupt::Yield(Yield::Balance(a)) => assert_eq!(a, to_address(1.into())),
+                i => panic!("unexpected interrupt {:?}", i),
+            }
+            assert_eq!(gas_used(&m), 3 + 100 + cold);
+        }
+    }
+
+    #[test]
+    fn sload_access() {
+        for (access, cold) in &[
+            (AccessStatus::EVMC_ACCESS_WARM, 0),
+            (AccessStatus::EVMC_ACCESS_COLD, 2000),
+        ] {
+            let frame = Frame::new(Fork::Berlin, &[0x60, 0x01, 0x54]);
+            let mut m = frame.machine();
+
+            match m.run() {
+                Interrupt::Yield(Yield::AccessStorage(k)) => assert_eq!(k, 1.into()),
+                i => panic!("unexpected interrupt {:?}", i),
+            }
+            assert_eq!(gas_used(&m), 3 + 100);
+
+            m.access = Some(*access);
+            match m.run() {
+                Interrupt::Yield(Yield::Load(k)) => assert_eq!(k, 1.into()),
+                i => panic!("unexpected interrupt {:?}", i),
+            }
+            assert_eq!(gas_used(&m), 3 + 100 + cold);
+        }
+    }
+
+    #[test]
+    fn sstore_access() {
+        for (access, cold) in &[
+            (AccessStatus::EVMC_ACCESS_WARM, 0),
+            (AccessStatus::EVMC_ACCESS_COLD, 2100),
+        ] {
+            // SSTORE(1, 2)
+            let frame = Frame::new(Fork::Berlin, &[0x60, 0x02, 0x60, 0x01, 0x55]);
+            let mut m = frame.machine();
+
+            match m.run() {
+                Interrupt::Yield(Yield::AccessStorage(k)) => assert_eq!(k, 1.into()),
+                i => panic!("unexpected interrupt {:?}", i),
+            }
+            assert_eq!(gas_used(&m), 3 + 3);
+
+            m.access = Some(*access);
+            match m.run() {
+                Interrupt::Yield(Yield::Store(k, v)) => {
+                    assert_eq!(k, 1.into());
+                    assert_eq!(v, 2.into());
+                }
+                i => panic!("unexpected interrupt {:?}", i),
+            }
+            assert_eq!(gas_used(&m), 3 + 3 + cold);
+
+            // the cold slot has been paid for, what is left of the store is priced as warm
+            m.charge_sstore(StorageStatus::EVMC_STORAGE_MODIFIED)
+                .unwrap();
+            assert_eq!(gas_used(&m), 3 + 3 + cold + 5000 - 2100);
+        }
+    }
+
+    #[test]
+    fn call_access() {
+        for (access, cold) in &[
+            (AccessStatus::EVMC_ACCESS_WARM, 0),
+            (AccessStatus::EVMC_ACCESS_COLD, 2500),
+        ] {
+            let frame = Frame::new(Fork::Berlin, &call(Op::Call, 0x10, 0x01, 5));
+            let mut m = frame.machine();
+
+            match m.run() {
+                Interrupt::Yield(Yield::AccessAccount(a)) => assert_eq!(a, to_address(1.into())),
+                i => panic!("unexpected interrupt {:?}", i),
+            }
+            assert_eq!(gas_used(&m), 7 * 3 + 100);
+
+            m.access = Some(*access);
+            match m.run() {
+                Interrupt::Yield(Yield::AccountExists(a)) => assert_eq!(a, to_address(1.into())),
+                i => panic!("unexpected interrupt {:?}", i),
+            }
+            assert_eq!(gas_used(&m), 7 * 3 + 100);
+
+            m.exists = Some(false);
+            match m.run() {
+                Interrupt::Yield(Yield::AccountBalance(a)) => assert_eq!(a, Address::default()),
+                i => panic!("unexpected interrupt {:?}", i),
+            }
+            assert_eq!(gas_used(&m), 7 * 3 + 100);
+
+            // every answer is kept until the call goes out
+            m.balance = Some(5.into());
+            let child = called(m.run());
+
+            assert_eq!(child.gas(), 0x10 + 2300);
+            assert_eq!(gas_used(&m), 7 * 3 + 100 + cold + 9000 + 25000 + 0x10);
+        }
+    }
+
+    // runs `op` with `shift` on top of `value`
+    fn shift(op: Op, value: U256, shift: U256) -> U256 {
+        let mut code = vec![0x7F];
+        code.extend_from_slice(&<[u8; 32]>::from(value));
+        code.push(0x7F);
+        code.extend_from_slice(&<[u8; 32]>::from(shift));
+        code.push(op as u8);
+
+        let frame = Frame::new(Fork::Constantinople, &code);
+        let mut m = frame.machine();
+
+        assert_eq!(exit(m.run()), Exit::Ret(0.into(), 0.into()));
+        assert_eq!(gas_used(&m), 3 + 3 + 3);
+        assert_eq!(m.stack.len(), 1);
+
+        m.stack[0]
+    }
+
+    #[test]
+    fn shl() {
+        let one = U256::one();
+        let min = U256::from("8000000000000000000000000000000000000000000000000000000000000000");
+
+        assert_eq!(shift(Op::Shl, one, 0.into()), one);
+        assert_eq!(shift(Op::Shl, one, 1.into()), 2.into());
+        assert_eq!(shift(Op::Shl, one, 0xFF.into()), min);
+        assert_eq!(shift(Op::Shl, one, 0x100.into()), U256::zero());
+        assert_eq!(shift(Op::Shl, one, 0x101.into()), U256::zero());
+        assert_eq!(shift(Op::Shl, U256::MAX, 0.into()), U256::MAX);
+        assert_eq!(shift(Op::Shl, U256::MAX, 1.into()), U256::MAX - 1);
+        assert_eq!(shift(Op::Shl, U256::MAX, 0xFF.into()), min);
+        assert_eq!(shift(Op::Shl, U256::MAX, 0x100.into()), U256::zero());
+        assert_eq!(shift(Op::Shl, U256::zero(), 1.into()), U256::zero());
+        assert_eq!(shift(Op::Shl, U256::MAX >> 1, 1.into()), U256::MAX - 1);
+        assert_eq!(shift(Op::Shl, one, U256::one() << 64), U256::zero());
+        assert_eq!(shift(Op::Shl, U256::MAX, U256::MAX), U256::zero());
+    }
+
+    #[test]
+    fn shr() {
+        let one = U256::one();
+        let min = U256::from("8000000000000000000000000000000000000000000000000000000000000000");
+
+        assert_eq!(shift(Op::Shr, one, 0.into()), one);
+        assert_eq!(shift(Op::Shr, one, 1.into()), U256::zero());
+        assert_eq!(shift(Op::Shr, min, 1.into()), min >> 1);
+        assert_eq!(shift(Op::Shr, min, 0xFF.into()), one);
+        assert_eq!(shift(Op::Shr, min, 0x100.into()), U256::zero());
+        assert_eq!(shift(Op::Shr, min, 0x101.into()), U256::zero());
+        assert_eq!(shift(Op::Shr, U256::MAX, 0.into()), U256::MAX);
+        assert_eq!(shift(Op::Shr, U256::MAX, 1.into()), U256::MAX >> 1);
+        assert_eq!(shift(Op::Shr, U256::MAX, 0xFF.into()), one);
+        assert_eq!(shift(Op::Shr, U256::MAX, 0x100.into()), U256::zero());
+        assert_eq!(shift(Op::Shr, U256::zero(), 1.into()), U256::zero());
+        assert_eq!(shift(Op::Shr, U256::MAX, U256::one() << 64), U256::zero());
+        assert_eq!(shift(Op::Shr, U256::MAX, U256::MAX), U256::zero());
+    }
+
+    #[test]
+    fn sar() {
+        let one = U256::one();
+        let min = U256::from("8000000000000000000000000000000000000000000000000000000000000000");
+        let max = U256::MAX >> 1;
+        let minus_one = U256::MAX;
+
+        // I256::min
+        assert_eq!(shift(Op::Sar, min, 0.into()), min);
+        assert_eq!(
+            shift(Op::Sar, min, 1.into()),
+            U256::from("c000000000000000000000000000000000000000000000000000000000000000")
+        );
+        assert_eq!(shift(Op::Sar, min, 0xFF.into()), minus_one);
+        assert_eq!(shift(Op::Sar, min, 0x100.into()), minus_one);
+        assert_eq!(shift(Op::Sar, min, 0x101.into()), minus_one);
+        assert_eq!(shift(Op::Sar, min, U256::one() << 64), minus_one);
+        assert_eq!(shift(Op::Sar, min, U256::MAX), minus_one);
+
+        // -1
+        assert_eq!(shift(Op::Sar, minus_one, 0.into()), minus_one);
+        assert_eq!(shift(Op::Sar, minus_one, 1.into()), minus_one);
+        assert_eq!(shift(Op::Sar, minus_one, 0xFF.into()), minus_one);
+        assert_eq!(shift(Op::Sar, minus_one, 0x100.into()), minus_one);
+        assert_eq!(shift(Op::Sar, minus_one, U256::one() << 64), minus_one);
+        assert_eq!(shift(Op::Sar, minus_one, U256::MAX), minus_one);
+
+        // positive values shift in zeros
+        assert_eq!(shift(Op::Sar, one, 0.into()), one);
+        assert_eq!(shift(Op::Sar, one, 1.into()), U256::zero());
+        assert_eq!(shift(Op::Sar, min >> 1, 0xFE.into()), one);
+        assert_eq!(shift(Op::Sar, max, 0xF8.into()), 0x7F.into());
+        assert_eq!(shift(Op::Sar, max, 0xFE.into()), one);
+        assert_eq!(shift(Op::Sar, max, 0xFF.into()), U256::zero());
+        assert_eq!(shift(Op::Sar, max, 0x100.into()), U256::zero());
+        assert_eq!(shift(Op::Sar, max, U256::one() << 64), U256::zero());
+        assert_eq!(shift(Op::Sar, U256::zero(), 1.into()), U256::zero());
+    }
+
+    #[test]
+    fn shifts_before_constantinople() {
+        for op in &[Op::Shl, Op::Shr, Op::Sar] {
+            let frame = Frame::new(Fork::Byzantium, &[0x60, 0x01, 0x60, 0x01, *op as u8]);
+            let mut m = frame.machine();
+
+            assert_eq!(exit(m.run()), Exit::InvalidOp);
+        }
+    }
+
+    #[test]
+    fn block_context() {
+        let env = Environment {
+            chain_id: 1.into(),
+            difficulty: 4.into(),
+            base_fee: 3.into(),
+            blob_base_fee: 5.into(),
+            ..Default::default()
+        };
+
+        // CHAINID, BASEFEE, DIFFICULTY, BLOBBASEFEE
+        let mut frame = Frame::new(Fork::Cancun, &[0x46, 0x48, 0x44, 0x4A]);
+        frame.env = env;
+        let mut m = frame.machine();
+
+        assert_eq!(exit(m.run()), Exit::Ret(0.into(), 0.into()));
+        assert_eq!(m.stack, vec![U256::from(1), 3.into(), 4.into(), 5.into()]);
+        assert_eq!(gas_used(&m), 4 * 2);
+    }
+
+    #[test]
+    fn block_context_forks() {
+        for (op, fork) in &[
+            (0x46, Fork::Petersburg),
+            (0x48, Fork::Berlin),
+            (0x4A, Fork::Shanghai),
+        ] {
+            let frame = Frame::new(*fork, &[*op]);
+            let mut m = frame.machine();
+
+            assert_eq!(exit(m.run()), Exit::InvalidOp);
+        }
+    }
+
+    #[test]
+    fn transient_storage() {
+        // TSTORE(1, 2), TLOAD(1)
+        let code = [0x60, 0x02, 0x60, 0x01, 0x5D, 0x60, 0x01, 0x5C];
+        let frame = Frame::new(Fork::Cancun, &code);
+        let mut m = frame.machine();
+
+        match m.run() {
+            Interrupt::Yield(Yield::TStore(k, v)) => {
+                assert_eq!(k, 1.into());
+                assert_eq!(v, 2.into());
+            }
+            i => panic!("unexpected interrupt {:?}", i),
+        }
+
+        match m.run() {
+            Interrupt::Yield(Yield::TLoad(k)) => assert_eq!(k, 1.into()),
+            i => panic!("unexpected interrupt {:?}", i),
+        }
+
+        assert_eq!(gas_used(&m), 3 + 3 + 100 + 3 + 100);
+    }
+
+    #[test]
+    fn transient_storage_static() {
+        let mut frame = Frame::new(Fork::Cancun, &[0x60, 0x02, 0x60, 0x01, 0x5D]);
+        frame.msg = message(MessageFlags::EVMC_STATIC as u32);
+        let mut m = frame.machine();
+
+        assert_eq!(exit(m.run()), Exit::StaticModeViolation);
+
+        // reads are fine
+        let mut frame = Frame::new(Fork::Cancun, &[0x60, 0x01, 0x5C]);
+        frame.msg = message(MessageFlags::EVMC_STATIC as u32);
+        let mut m = frame.machine();
+
+        assert!(matches!(m.run(), Interrupt::Yield(Yield::TLoad(_))));
+    }
+
+    #[test]
+    fn transient_storage_before_cancun() {
+        for op in &[0x5C, 0x5D] {
+            let frame = Frame::new(Fork::Shanghai, &[0x60, 0x01, 0x60, 0x01, *op]);
+            let mut m = frame.machine();
+
+            assert_eq!(exit(m.run()), Exit::InvalidOp);
+        }
+    }
+
+    // memory holds the bytes 1..=32 before MCOPY(dest, src, len) runs
+    fn mcopy(dest: u8, src: u8, len: u8) -> (Vec<u8>, u64) {
+        let mut code = vec![0x7F];
+        code.extend(1..=32);
+        code.extend_from_slice(&[0x60, 0x00, 0x52]);
+        code.extend_from_slice(&[0x60, len, 0x60, src, 0x60, dest, 0x5E]);
+
+        let frame = Frame::new(Fork::Cancun, &code);
+        let mut m = frame.machine();
+
+        assert_eq!(exit(m.run()), Exit::Ret(0.into(), 0.into()));
+
+        // the mstore and its word of memory come before the copy
+        (
+            m.memory.to_vec(),
+            gas_used(&m) - (3 + 3 + 3 + 3) - (3 + 3 + 3),
+        )
+    }
+
+    #[test]
+    fn mcopy_forward_overlap() {
+        let (memory, gas) = mcopy(1, 0, 8);
+
+        assert_eq!(&memory[..10], &[1, 1, 2, 3, 4, 5, 6, 7, 8, 10]);
+        assert_eq!(&memory[10..], &(11..=32).collect::<Vec<u8>>()[..]);
+        assert_eq!(gas, 3 + 3);
+    }
+
+    #[test]
+    fn mcopy_backward_overlap() {
+        let (memory, gas) = mcopy(0, 1, 8);
+
+        assert_eq!(&memory[..10], &[2, 3, 4, 5, 6, 7, 8, 9, 9, 10]);
+        assert_eq!(&memory[10..], &(11..=32).collect::<Vec<u8>>()[..]);
+        assert_eq!(gas, 3 + 3);
+    }
+
+    #[test]
+    fn mcopy_whole_word_in_place() {
+        let (memory, gas) = mcopy(0, 0, 32);
+
+        assert_eq!(memory, (1..=32).collect::<Vec<u8>>());
+        assert_eq!(gas, 3 + 3);
+    }
+
+    #[test]
+    fn mcopy_expands_memory() {
+        // the destination runs past the end of memory, the copy reads the word written before
+        let (memory, gas) = mcopy(16, 0, 32);
+
+        assert_eq!(memory.len(), 64);
+        assert_eq!(&memory[..16], &(1..=16).collect::<Vec<u8>>()[..]);
+        assert_eq!(&memory[16..48], &(1..=32).collect::<Vec<u8>>()[..]);
+        assert_eq!(&memory[48..], &[0; 16]);
+        assert_eq!(gas, 3 + 3 + 3);
+
+        // the source runs past the end of memory, what lies there reads as zeros
+        let (memory, gas) = mcopy(0, 16, 32);
+
+        assert_eq!(memory.len(), 64);
+        assert_eq!(&memory[..16], &(17..=32).collect::<Vec<u8>>()[..]);
+        assert_eq!(&memory[16..], &[0; 48]);
+        assert_eq!(gas, 3 + 3 + 3);
+    }
+
+    #[test]
+    fn mcopy_zero_length() {
+        let (memory, gas) = mcopy(0xFF, 0xFF, 0);
+
+        assert_eq!(memory.len(), 32);
+        assert_eq!(gas, 3);
+    }
+
+    #[test]
+    fn mcopy_before_cancun() {
+        let frame = Frame::new(Fork::Shanghai, &[0x60, 0x00, 0x80, 0x80, 0x5E]);
+        let mut m = frame.machine();
+
+        assert_eq!(exit(m.run()), Exit::InvalidOp);
+    }
+}
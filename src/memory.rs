@@ -0,0 +1,178 @@
+use crate::gas::GasSchedule;
+
+use std::cmp::min;
+use std::ops::{Deref, DerefMut};
+
+/// The volatile memory of a call frame. It is always a whole number of 32 byte words long, so its
+/// length doubles as the value reported by MSIZE.
+#[derive(Debug, Default)]
+pub struct Memory(Vec<u8>);
+
+impl Memory {
+    pub fn new() -> Self {
+        Self(vec![])
+    }
+
+    /// The number of active words.
+    pub fn words(&self) -> u64 {
+        (self.0.len() / 32) as u64
+    }
+
+    /// The number of words needed to access `len` bytes at `offset`.
+    fn words_for(offset: usize, len: usize) -> Option<u64> {
+        offset.checked_add(len).map(|end| (end as u64).div_ceil(32))
+    }
+
+    /// The gas needed to grow memory to cover `len` bytes at `offset`, or `None` if it can never
+    /// be paid for. An access of zero bytes never grows memory.
+    pub fn expansion_cost(&self, offset: usize, len: usize, schedule: &GasSchedule) -> Option<u64> {
+        if len == 0 {
+            return Some(0);
+        }
+
+        let new = Self::words_for(offset, len)?;
+        let old = self.words();
+
+        if new <= old {
+            return Some(0);
+        }
+
+        Some(schedule.memory_cost(new)? - schedule.memory_cost(old)?)
+    }
+
+    /// Grows memory to cover `len` bytes at `offset`, zero filling the new words. The expansion
+    /// must have been paid for with `expansion_cost` beforehand.
+    pub fn expand(&mut self, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+
+        let words = Self::words_for(offset, len).expect("memory expansion was paid for");
+
+        if words > self.words() {
+            self.0.resize(words as usize * 32, 0);
+        }
+    }
+
+    /// Copies `len` bytes of `data` starting at `data_offset` to `offset`, whatever lies past the
+    /// end of `data` is read as zeros. Memory must have been expanded to cover the copy.
+    pub fn copy_from(&mut self, offset: usize, data: &[u8], data_offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+
+        let dest = &mut self.0[offset..offset + len];
+        let src = data.get(data_offset..).unwrap_or_default();
+        let copied = min(len, src.len());
+
+        dest[..copied].copy_from_slice(&src[..copied]);
+        for b in dest[copied..].iter_mut() {
+            *b = 0;
+        }
+    }
+}
+
+impl Deref for Memory {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl DerefMut for Memory {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expanded(words: usize) -> Memory {
+        let mut memory = Memory::new();
+        memory.expand(0, words * 32);
+        memory
+    }
+
+    #[test]
+    fn memory_cost() {
+        let schedule = GasSchedule::london();
+
+        assert_eq!(schedule.memory_cost(0), Some(0));
+        assert_eq!(schedule.memory_cost(1), Some(3));
+        assert_eq!(schedule.memory_cost(32), Some(98));
+        assert_eq!(schedule.memory_cost(1024), Some(5120));
+        assert_eq!(schedule.memory_cost(u64::MAX), None);
+    }
+
+    #[test]
+    fn expansion_cost_is_the_difference() {
+        let schedule = GasSchedule::london();
+
+        assert_eq!(expanded(0).expansion_cost(0, 32, &schedule), Some(3));
+        assert_eq!(expanded(1).expansion_cost(0, 96, &schedule), Some(9 - 3));
+        assert_eq!(
+            expanded(512).expansion_cost(0, 1024 * 32, &schedule),
+            Some(5120 - 2048)
+        );
+    }
+
+    #[test]
+    fn expansion_rounds_up_to_words() {
+        let schedule = GasSchedule::london();
+        let memory = expanded(0);
+
+        assert_eq!(memory.expansion_cost(0, 1, &schedule), Some(3));
+        assert_eq!(memory.expansion_cost(1, 31, &schedule), Some(3));
+        assert_eq!(memory.expansion_cost(0, 33, &schedule), Some(6));
+        assert_eq!(memory.expansion_cost(31, 2, &schedule), Some(6));
+
+        let mut memory = expanded(0);
+        memory.expand(31, 2);
+        assert_eq!(memory.len(), 64);
+        assert_eq!(memory.words(), 2);
+    }
+
+    #[test]
+    fn expansion_within_memory_is_free() {
+        let schedule = GasSchedule::london();
+        let memory = expanded(2);
+
+        assert_eq!(memory.expansion_cost(0, 64, &schedule), Some(0));
+        assert_eq!(memory.expansion_cost(63, 1, &schedule), Some(0));
+        assert_eq!(memory.expansion_cost(64, 1, &schedule), Some(3 * 3 - 2 * 3));
+    }
+
+    #[test]
+    fn zero_length_does_not_expand() {
+        let schedule = GasSchedule::london();
+        let mut memory = expanded(0);
+
+        assert_eq!(memory.expansion_cost(usize::MAX, 0, &schedule), Some(0));
+        assert_eq!(memory.expansion_cost(1024, 0, &schedule), Some(0));
+
+        memory.expand(1024, 0);
+        assert_eq!(memory.len(), 0);
+    }
+
+    #[test]
+    fn expansion_past_usize() {
+        let schedule = GasSchedule::london();
+
+        assert_eq!(expanded(0).expansion_cost(usize::MAX, 1, &schedule), None);
+    }
+
+    #[test]
+    fn copy_pads_with_zeros() {
+        let mut memory = expanded(1);
+        memory[..4].copy_from_slice(&[0xFF; 4]);
+
+        memory.copy_from(0, &[1, 2, 3], 1, 4);
+        assert_eq!(&memory[..5], &[2, 3, 0, 0, 0]);
+
+        memory.copy_from(0, &[1, 2, 3], 8, 2);
+        assert_eq!(&memory[..2], &[0, 0]);
+    }
+}